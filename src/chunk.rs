@@ -0,0 +1,148 @@
+use crate::error::Span;
+use crate::expr::LiteralValue;
+use crate::process::Command;
+use std::rc::Rc;
+
+/// Instructions understood by the bytecode `Vm`.
+///
+/// Operands are stored inline: constant and name operands index into
+/// `Chunk::constants`, `Closure` indexes into `Chunk::functions`, `Command`
+/// indexes into `Chunk::commands`, slot
+/// operands index into the current call frame, and jump operands are absolute
+/// offsets into `Chunk::code`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpCode {
+    Constant(usize),
+    Nil,
+    True,
+    False,
+    Pop,
+    // Pushes a copy of the value this many slots below the top of the stack
+    Dup(usize),
+    // Discards this many values beneath the top of the stack, keeping the top
+    PopBelow(usize),
+    GetLocal(usize),
+    SetLocal(usize),
+    GetGlobal(usize),
+    DefineGlobal(usize),
+    SetGlobal(usize),
+    GetUpvalue(usize),
+    SetUpvalue(usize),
+    GetProperty(usize),
+    SetProperty(usize),
+    GetSuper(usize),
+    BuildList(usize),
+    BuildMap(usize),
+    GetIndex,
+    SetIndex,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump(usize),
+    JumpIfFalse(usize),
+    Call(usize),
+    Closure(usize),
+    CloseUpvalue,
+    Return,
+    Class(usize),
+    Inherit,
+    Method(usize),
+    // Pushes the exports of the module whose path is this constant
+    Import(usize),
+    // Pushes a function that runs this command under the policy of the VM
+    Command(usize),
+}
+
+#[derive(Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    pub spans: Vec<Span>,
+    pub constants: Vec<LiteralValue>,
+    pub functions: Vec<Rc<FunctionProto>>,
+    pub commands: Vec<Command>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, op: OpCode, span: Span) -> usize {
+        self.code.push(op);
+        self.spans.push(span);
+        self.code.len() - 1
+    }
+
+    pub fn add_constant(&mut self, value: LiteralValue) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    pub fn add_function(&mut self, function: FunctionProto) -> usize {
+        self.functions.push(Rc::new(function));
+        self.functions.len() - 1
+    }
+
+    pub fn add_command(&mut self, command: Command) -> usize {
+        self.commands.push(command);
+        self.commands.len() - 1
+    }
+
+    #[allow(dead_code)]
+    pub fn disassemble(&self, name: &str) -> String {
+        let mut result = format!("== {name} ==\n");
+        for (offset, op) in self.code.iter().enumerate() {
+            let line = if offset > 0 && self.spans[offset].line == self.spans[offset - 1].line {
+                "   |".to_string()
+            } else {
+                format!("{:4}", self.spans[offset].line)
+            };
+            let operand = match op {
+                OpCode::Constant(idx)
+                | OpCode::GetGlobal(idx)
+                | OpCode::DefineGlobal(idx)
+                | OpCode::SetGlobal(idx)
+                | OpCode::GetProperty(idx)
+                | OpCode::SetProperty(idx)
+                | OpCode::GetSuper(idx)
+                | OpCode::Class(idx)
+                | OpCode::Method(idx)
+                | OpCode::Import(idx) => format!(" '{}'", self.constants[*idx].to_string()),
+                OpCode::Closure(idx) => format!(" <fn {}>", self.functions[*idx].name),
+                OpCode::Command(idx) => format!(" {:?}", self.commands[*idx].argv),
+                _ => String::new(),
+            };
+            result.push_str(&format!("{offset:04} {line} {op:?}{operand}\n"));
+        }
+
+        result
+    }
+}
+
+/// A function compiled to bytecode. Closures over it are created at runtime
+/// by `OpCode::Closure`, which captures the upvalues described here.
+pub struct FunctionProto {
+    pub name: String,
+    pub arity: usize,
+    pub chunk: Chunk,
+    pub upvalues: Vec<UpvalueRef>,
+}
+
+/// Where a closure finds a captured variable when it is created: either a
+/// local slot of the enclosing frame or one of the enclosing closure's
+/// upvalues.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpvalueRef {
+    pub index: usize,
+    pub is_local: bool,
+}
@@ -0,0 +1,813 @@
+use crate::chunk::{Chunk, FunctionProto, OpCode, UpvalueRef};
+use crate::error::{ErrorKind, MossError, Span};
+use crate::expr::{update_operator, Expr, LiteralValue};
+use crate::process::Command;
+use crate::scanner::{Token, TokenType};
+use crate::stmt::Stmt;
+
+#[derive(Copy, Clone, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    is_captured: bool,
+}
+
+// The jumps out of a loop body that are patched once the loop is compiled
+struct LoopState {
+    scope_depth: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+struct FunctionState {
+    proto: FunctionProto,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    loops: Vec<LoopState>,
+}
+
+impl FunctionState {
+    fn new(name: &str, arity: usize, kind: FunctionKind) -> Self {
+        // Slot 0 holds the callee itself, or the receiver inside methods
+        let slot_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+
+        Self {
+            proto: FunctionProto {
+                name: name.to_string(),
+                arity,
+                chunk: Chunk::new(),
+                upvalues: vec![],
+            },
+            kind,
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: 0,
+                is_captured: false,
+            }],
+            scope_depth: 0,
+            loops: vec![],
+        }
+    }
+}
+
+/// Compiles a resolved AST into bytecode for the `Vm`.
+///
+/// The tree-walking `Interpreter` stays the reference implementation, so the
+/// compiled code mirrors its semantics (and its error messages) rather than
+/// clox's where the two differ.
+pub struct Compiler {
+    states: Vec<FunctionState>,
+    span: Span,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self {
+            states: vec![FunctionState::new("script", 0, FunctionKind::Script)],
+            span: Span {
+                line: 1,
+                ..Span::default()
+            },
+        }
+    }
+
+    pub fn compile(mut self, stmts: &Vec<&Stmt>) -> Result<FunctionProto, MossError> {
+        for (i, stmt) in stmts.iter().enumerate() {
+            // The value of a trailing expression statement is the result of
+            // the script, which the REPL shows
+            let result = match stmt {
+                Stmt::Expression { expression } if i + 1 == stmts.len() => {
+                    self.expression(expression).map(|_| {
+                        self.emit(OpCode::Return);
+                    })
+                }
+                _ => self.statement(stmt),
+            };
+            // Errors point at the last token the compiler looked at
+            result.map_err(|msg| MossError::new(ErrorKind::Compile, msg, Some(self.span)))?;
+        }
+        self.emit_return();
+
+        Ok(self.states.pop().expect("Compiler state underflow").proto)
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), String> {
+        match stmt {
+            Stmt::Expression { expression } => {
+                self.expression(expression)?;
+                self.emit(OpCode::Pop);
+            }
+            Stmt::Print { expression } => {
+                self.expression(expression)?;
+                self.emit(OpCode::Print);
+            }
+            Stmt::Var { name, initializer } => {
+                self.span = name.span();
+                self.expression(initializer)?;
+                self.define_variable(name);
+            }
+            Stmt::Block { statements } => {
+                self.begin_scope();
+                for statement in statements {
+                    self.statement(statement)?;
+                }
+                self.end_scope();
+            }
+            Stmt::Class {
+                name,
+                methods,
+                superclass,
+            } => self.class_declaration(name, methods, superclass.as_ref())?,
+            Stmt::IfStmt {
+                predicate,
+                then,
+                els,
+            } => {
+                self.expression(predicate)?;
+                let then_jump = self.emit(OpCode::JumpIfFalse(0));
+                self.emit(OpCode::Pop);
+                self.statement(then)?;
+                let else_jump = self.emit(OpCode::Jump(0));
+                self.patch_jump(then_jump);
+                self.emit(OpCode::Pop);
+                if let Some(els) = els {
+                    self.statement(els)?;
+                }
+                self.patch_jump(else_jump);
+            }
+            Stmt::WhileStmt {
+                condition,
+                body,
+                increment,
+            } => {
+                let loop_start = self.chunk().code.len();
+                self.expression(condition)?;
+                let exit_jump = self.emit(OpCode::JumpIfFalse(0));
+                self.emit(OpCode::Pop);
+
+                let scope_depth = self.state().scope_depth;
+                self.state_mut().loops.push(LoopState {
+                    scope_depth,
+                    breaks: vec![],
+                    continues: vec![],
+                });
+                self.statement(body)?;
+                let finished = self.state_mut().loops.pop().unwrap();
+
+                for jump in finished.continues {
+                    self.patch_jump(jump);
+                }
+                if let Some(increment) = increment {
+                    self.expression(increment)?;
+                    self.emit(OpCode::Pop);
+                }
+                self.emit(OpCode::Jump(loop_start));
+                self.patch_jump(exit_jump);
+                self.emit(OpCode::Pop);
+                for jump in finished.breaks {
+                    self.patch_jump(jump);
+                }
+            }
+            Stmt::Break { keyword } => {
+                self.span = keyword.span();
+                let jump = self.exit_loop_body();
+                self.state_mut().loops.last_mut().unwrap().breaks.push(jump);
+            }
+            Stmt::Continue { keyword } => {
+                self.span = keyword.span();
+                let jump = self.exit_loop_body();
+                self.state_mut()
+                    .loops
+                    .last_mut()
+                    .unwrap()
+                    .continues
+                    .push(jump);
+            }
+            Stmt::Function { name, params, body } => {
+                self.span = name.span();
+                // Locals are declared before the body is compiled so the function can
+                // refer to itself
+                let global = self.state().scope_depth == 0;
+                if !global {
+                    self.add_local(&name.lexeme);
+                }
+                self.function(&name.lexeme, params, body, FunctionKind::Function)?;
+                if global {
+                    let idx = self.identifier_constant(&name.lexeme);
+                    self.emit(OpCode::DefineGlobal(idx));
+                }
+            }
+            Stmt::CmdFunction { name, params, argv } => {
+                self.span = name.span();
+                let idx = self.chunk().add_command(Command {
+                    name: name.lexeme.clone(),
+                    argv: argv.clone(),
+                    arity: params.len(),
+                });
+                self.emit(OpCode::Command(idx));
+                self.define_variable(name);
+            }
+            Stmt::Import {
+                keyword,
+                name,
+                path,
+            } => {
+                self.span = keyword.span();
+                let idx = self.identifier_constant(path);
+                self.emit(OpCode::Import(idx));
+                self.define_variable(name);
+            }
+            // The exports of a module are collected once it has finished running
+            Stmt::Export { name: _ } => (),
+            Stmt::ReturnStmt { keyword, value } => {
+                self.span = keyword.span();
+                if self.state().kind == FunctionKind::Initializer {
+                    // Constructors always hand back the instance
+                    if let Some(value) = value {
+                        self.expression(value)?;
+                        self.emit(OpCode::Pop);
+                    }
+                    self.emit(OpCode::GetLocal(0));
+                } else if let Some(value) = value {
+                    self.expression(value)?;
+                } else {
+                    self.emit(OpCode::Nil);
+                }
+                self.emit(OpCode::Return);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[allow(clippy::vec_box)]
+    fn class_declaration(
+        &mut self,
+        name: &Token,
+        methods: &Vec<Box<Stmt>>,
+        superclass: Option<&Expr>,
+    ) -> Result<(), String> {
+        self.span = name.span();
+        let name_idx = self.identifier_constant(&name.lexeme);
+
+        // A local class gets its slot up front, so methods can capture it while the
+        // class is still being built on top of the stack
+        let global = self.state().scope_depth == 0;
+        let slot = if global {
+            None
+        } else {
+            self.emit(OpCode::Nil);
+            self.add_local(&name.lexeme);
+            Some(self.state().locals.len() - 1)
+        };
+
+        if let Some(superclass) = superclass {
+            self.begin_scope();
+            self.expression(superclass)?;
+            self.add_local("super");
+        }
+
+        self.emit(OpCode::Class(name_idx));
+        if superclass.is_some() {
+            self.emit(OpCode::Inherit);
+        }
+
+        for method in methods {
+            if let Stmt::Function { name, params, body } = method.as_ref() {
+                self.span = name.span();
+                let kind = if name.lexeme == "init" {
+                    FunctionKind::Initializer
+                } else {
+                    FunctionKind::Method
+                };
+                self.function(&name.lexeme, params, body, kind)?;
+                let idx = self.identifier_constant(&name.lexeme);
+                self.emit(OpCode::Method(idx));
+            } else {
+                return Err(
+                    "Something that was not a function was in the methods of a class".to_string(),
+                );
+            }
+        }
+
+        match slot {
+            None => {
+                self.emit(OpCode::DefineGlobal(name_idx));
+            }
+            Some(slot) => {
+                self.emit(OpCode::SetLocal(slot));
+                self.emit(OpCode::Pop);
+            }
+        }
+
+        if superclass.is_some() {
+            self.end_scope();
+        }
+
+        Ok(())
+    }
+
+    #[allow(clippy::vec_box)]
+    fn function(
+        &mut self,
+        name: &str,
+        params: &Vec<Token>,
+        body: &Vec<Box<Stmt>>,
+        kind: FunctionKind,
+    ) -> Result<(), String> {
+        self.states
+            .push(FunctionState::new(name, params.len(), kind));
+        self.begin_scope();
+        for param in params {
+            self.add_local(&param.lexeme);
+        }
+        for stmt in body {
+            self.statement(stmt)?;
+        }
+        self.emit_return();
+
+        let state = self.states.pop().expect("Compiler state underflow");
+        let idx = self.chunk().add_function(state.proto);
+        self.emit(OpCode::Closure(idx));
+
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<(), String> {
+        match expr {
+            Expr::AnonFunction {
+                id: _,
+                paren,
+                arguments,
+                body,
+            } => {
+                self.span = paren.span();
+                self.function("anon_funciton", arguments, body, FunctionKind::Function)?;
+            }
+            Expr::Array { id: _, elements } => {
+                for element in elements {
+                    self.expression(element)?;
+                }
+                self.emit(OpCode::BuildList(elements.len()));
+            }
+            Expr::Assign { id: _, name, value } => {
+                self.expression(value)?;
+                self.span = name.span();
+                self.set_variable(&name.lexeme);
+            }
+            Expr::Binary {
+                id: _,
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left)?;
+                self.expression(right)?;
+                self.span = operator.span();
+                let op = binary_opcode(operator.token_type)?;
+                self.emit(op);
+            }
+            Expr::Call {
+                id: _,
+                callee,
+                paren,
+                arguments,
+            } => {
+                self.expression(callee)?;
+                for argument in arguments {
+                    self.expression(argument)?;
+                }
+                self.span = paren.span();
+                self.emit(OpCode::Call(arguments.len()));
+            }
+            Expr::Get {
+                id: _,
+                object,
+                name,
+            } => {
+                self.expression(object)?;
+                self.span = name.span();
+                let idx = self.identifier_constant(&name.lexeme);
+                self.emit(OpCode::GetProperty(idx));
+            }
+            Expr::Grouping { id: _, expression } => self.expression(expression)?,
+            Expr::Index {
+                id: _,
+                object,
+                bracket,
+                index,
+            } => {
+                self.expression(object)?;
+                self.expression(index)?;
+                self.span = bracket.span();
+                self.emit(OpCode::GetIndex);
+            }
+            Expr::IndexSet {
+                id: _,
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                self.expression(object)?;
+                self.expression(index)?;
+                self.expression(value)?;
+                self.span = bracket.span();
+                self.emit(OpCode::SetIndex);
+            }
+            Expr::Literal { id: _, value } => match value {
+                LiteralValue::Nil => {
+                    self.emit(OpCode::Nil);
+                }
+                LiteralValue::True => {
+                    self.emit(OpCode::True);
+                }
+                LiteralValue::False => {
+                    self.emit(OpCode::False);
+                }
+                value => {
+                    let idx = self.chunk().add_constant(value.clone());
+                    self.emit(OpCode::Constant(idx));
+                }
+            },
+            Expr::Object { id: _, entries } => {
+                for (key, value) in entries {
+                    let key = self.identifier_constant(key);
+                    self.emit(OpCode::Constant(key));
+                    self.expression(value)?;
+                }
+                self.emit(OpCode::BuildMap(entries.len()));
+            }
+            Expr::Logical {
+                id: _,
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left)?;
+                self.span = operator.span();
+                match operator.token_type {
+                    TokenType::Or => {
+                        let else_jump = self.emit(OpCode::JumpIfFalse(0));
+                        let end_jump = self.emit(OpCode::Jump(0));
+                        self.patch_jump(else_jump);
+                        self.emit(OpCode::Pop);
+                        self.expression(right)?;
+                        self.patch_jump(end_jump);
+                    }
+                    TokenType::And => {
+                        // A falsy left operand short-circuits to `false`, like the interpreter
+                        let false_jump = self.emit(OpCode::JumpIfFalse(0));
+                        self.emit(OpCode::Pop);
+                        self.expression(right)?;
+                        let end_jump = self.emit(OpCode::Jump(0));
+                        self.patch_jump(false_jump);
+                        self.emit(OpCode::Pop);
+                        self.emit(OpCode::False);
+                        self.patch_jump(end_jump);
+                    }
+                    ttype => return Err(format!("Invalid token in logical expression: {}", ttype)),
+                }
+            }
+            Expr::Set {
+                id: _,
+                object,
+                name,
+                value,
+            } => {
+                self.expression(object)?;
+                self.expression(value)?;
+                self.span = name.span();
+                let idx = self.identifier_constant(&name.lexeme);
+                self.emit(OpCode::SetProperty(idx));
+            }
+            Expr::This { id: _, keyword } => {
+                self.span = keyword.span();
+                self.get_variable("this");
+            }
+            Expr::Super {
+                id: _,
+                keyword,
+                method,
+            } => {
+                self.span = keyword.span();
+                self.get_variable("this");
+                self.get_variable("super");
+                let idx = self.identifier_constant(&method.lexeme);
+                self.emit(OpCode::GetSuper(idx));
+            }
+            Expr::Update {
+                id: _,
+                target,
+                operator,
+                value,
+                postfix,
+            } => self.update(target, operator, value, *postfix)?,
+            Expr::Unary {
+                id: _,
+                operator,
+                right,
+            } => {
+                self.expression(right)?;
+                self.span = operator.span();
+                match operator.token_type {
+                    TokenType::Minus => self.emit(OpCode::Negate),
+                    TokenType::Bang => self.emit(OpCode::Not),
+                    ttype => return Err(format!("{} is not a valid unary operator", ttype)),
+                };
+            }
+            Expr::Variable { id: _, name } => {
+                self.span = name.span();
+                self.get_variable(&name.lexeme);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn define_variable(&mut self, name: &Token) {
+        if self.state().scope_depth == 0 {
+            let idx = self.identifier_constant(&name.lexeme);
+            self.emit(OpCode::DefineGlobal(idx));
+        } else {
+            // The value on top of the stack becomes the local's slot
+            self.add_local(&name.lexeme);
+        }
+    }
+
+    /// Compiles `target op= value`, `++target` or `target++`. The object and
+    /// index of a property or index target are evaluated once and then copied
+    /// with `Dup`, so that the result is left on the stack beneath them.
+    fn update(
+        &mut self,
+        target: &Expr,
+        operator: &Token,
+        value: &Expr,
+        postfix: bool,
+    ) -> Result<(), String> {
+        let op = binary_opcode(update_operator(operator.token_type))?;
+        if let Expr::Variable { id: _, name } = target {
+            self.get_variable(&name.lexeme);
+            if postfix {
+                self.emit(OpCode::Dup(0));
+            }
+            self.expression(value)?;
+            self.span = operator.span();
+            self.emit(op);
+            self.set_variable(&name.lexeme);
+            if postfix {
+                self.emit(OpCode::Pop);
+            }
+            return Ok(());
+        }
+
+        let (operands, get, set) = match target {
+            Expr::Get {
+                id: _,
+                object,
+                name,
+            } => {
+                self.expression(object)?;
+                let idx = self.identifier_constant(&name.lexeme);
+                (1, OpCode::GetProperty(idx), OpCode::SetProperty(idx))
+            }
+            Expr::Index {
+                id: _,
+                object,
+                bracket: _,
+                index,
+            } => {
+                self.expression(object)?;
+                self.expression(index)?;
+                (2, OpCode::GetIndex, OpCode::SetIndex)
+            }
+            _ => return Err(format!("Invalid target for '{}'", operator.lexeme)),
+        };
+        self.span = operator.span();
+
+        // [operands] -> [operands old]
+        for _ in 0..operands {
+            self.emit(OpCode::Dup(operands - 1));
+        }
+        self.emit(get);
+        if !postfix {
+            // -> [operands new]
+            self.expression(value)?;
+            self.span = operator.span();
+            self.emit(op);
+        }
+        // -> [operands result operands new]
+        for _ in 0..=operands {
+            self.emit(OpCode::Dup(operands));
+        }
+        if postfix {
+            self.expression(value)?;
+            self.span = operator.span();
+            self.emit(op);
+        }
+        // -> [result]
+        self.emit(set);
+        self.emit(OpCode::Pop);
+        self.emit(OpCode::PopBelow(operands));
+
+        Ok(())
+    }
+
+    fn get_variable(&mut self, name: &str) {
+        let top = self.states.len() - 1;
+        let op = if let Some(slot) = self.resolve_local(top, name) {
+            OpCode::GetLocal(slot)
+        } else if let Some(idx) = self.resolve_upvalue(top, name) {
+            OpCode::GetUpvalue(idx)
+        } else {
+            OpCode::GetGlobal(self.identifier_constant(name))
+        };
+        self.emit(op);
+    }
+
+    fn set_variable(&mut self, name: &str) {
+        let top = self.states.len() - 1;
+        let op = if let Some(slot) = self.resolve_local(top, name) {
+            OpCode::SetLocal(slot)
+        } else if let Some(idx) = self.resolve_upvalue(top, name) {
+            OpCode::SetUpvalue(idx)
+        } else {
+            OpCode::SetGlobal(self.identifier_constant(name))
+        };
+        self.emit(op);
+    }
+
+    fn resolve_local(&self, state: usize, name: &str) -> Option<usize> {
+        self.states[state]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name)
+    }
+
+    fn resolve_upvalue(&mut self, state: usize, name: &str) -> Option<usize> {
+        if state == 0 {
+            return None;
+        }
+
+        if let Some(slot) = self.resolve_local(state - 1, name) {
+            self.states[state - 1].locals[slot].is_captured = true;
+            return Some(self.add_upvalue(state, slot, true));
+        }
+
+        if let Some(idx) = self.resolve_upvalue(state - 1, name) {
+            return Some(self.add_upvalue(state, idx, false));
+        }
+
+        None
+    }
+
+    fn add_upvalue(&mut self, state: usize, index: usize, is_local: bool) -> usize {
+        let upvalue = UpvalueRef { index, is_local };
+        let upvalues = &mut self.states[state].proto.upvalues;
+        if let Some(existing) = upvalues.iter().position(|u| *u == upvalue) {
+            return existing;
+        }
+        upvalues.push(upvalue);
+        upvalues.len() - 1
+    }
+
+    fn add_local(&mut self, name: &str) {
+        let state = self.state_mut();
+        let depth = state.scope_depth;
+        state.locals.push(Local {
+            name: name.to_string(),
+            depth,
+            is_captured: false,
+        });
+    }
+
+    /// Discards the locals of the innermost loop body without ending their
+    /// scopes, then emits a jump to be patched by the loop.
+    fn exit_loop_body(&mut self) -> usize {
+        let state = self.state();
+        let depth = state
+            .loops
+            .last()
+            .expect("Resolver allowed break or continue outside of a loop")
+            .scope_depth;
+        let ops: Vec<OpCode> = state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .map(|local| {
+                if local.is_captured {
+                    OpCode::CloseUpvalue
+                } else {
+                    OpCode::Pop
+                }
+            })
+            .collect();
+
+        for op in ops {
+            self.emit(op);
+        }
+        self.emit(OpCode::Jump(0))
+    }
+
+    fn begin_scope(&mut self) {
+        self.state_mut().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let state = self.state_mut();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+
+        let mut ops = vec![];
+        while let Some(local) = state.locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+            ops.push(if local.is_captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            });
+            state.locals.pop();
+        }
+
+        for op in ops {
+            self.emit(op);
+        }
+    }
+
+    fn identifier_constant(&mut self, name: &str) -> usize {
+        let chunk = self.chunk();
+        let existing = chunk.constants.iter().position(|constant| match constant {
+            LiteralValue::StringValue(s) => s == name,
+            _ => false,
+        });
+
+        match existing {
+            Some(idx) => idx,
+            None => chunk.add_constant(LiteralValue::StringValue(name.to_string())),
+        }
+    }
+
+    fn emit(&mut self, op: OpCode) -> usize {
+        let span = self.span;
+        self.chunk().write(op, span)
+    }
+
+    fn emit_return(&mut self) {
+        if self.state().kind == FunctionKind::Initializer {
+            self.emit(OpCode::GetLocal(0));
+        } else {
+            self.emit(OpCode::Nil);
+        }
+        self.emit(OpCode::Return);
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        let target = self.chunk().code.len();
+        match &mut self.chunk().code[offset] {
+            OpCode::Jump(to) | OpCode::JumpIfFalse(to) => *to = target,
+            op => panic!("Tried to patch {op:?} as a jump"),
+        }
+    }
+
+    fn state(&self) -> &FunctionState {
+        self.states.last().expect("Compiler state underflow")
+    }
+
+    fn state_mut(&mut self) -> &mut FunctionState {
+        self.states.last_mut().expect("Compiler state underflow")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state_mut().proto.chunk
+    }
+}
+
+fn binary_opcode(operator: TokenType) -> Result<OpCode, String> {
+    match operator {
+        TokenType::Plus => Ok(OpCode::Add),
+        TokenType::Minus => Ok(OpCode::Subtract),
+        TokenType::Star => Ok(OpCode::Multiply),
+        TokenType::Slash => Ok(OpCode::Divide),
+        TokenType::EqualEqual => Ok(OpCode::Equal),
+        TokenType::BangEqual => Ok(OpCode::NotEqual),
+        TokenType::Greater => Ok(OpCode::Greater),
+        TokenType::GreaterEqual => Ok(OpCode::GreaterEqual),
+        TokenType::Less => Ok(OpCode::Less),
+        TokenType::LessEqual => Ok(OpCode::LessEqual),
+        ttype => Err(format!("{} is not a valid binary operator", ttype)),
+    }
+}
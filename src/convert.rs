@@ -0,0 +1,160 @@
+//! Conversions between moss values and Rust types, for hosts that pass values
+//! in and out of a program. Converting back out of a `LiteralValue` fails with
+//! an error message, so natives written in Rust can use `?` on it.
+
+use crate::expr::LiteralValue;
+use std::collections::HashMap;
+
+macro_rules! from_number {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for LiteralValue {
+                fn from(x: $t) -> Self {
+                    LiteralValue::Number(x as f64)
+                }
+            }
+        )*
+    };
+}
+
+from_number!(f64, f32, i32, i64, u32, u64, usize);
+
+impl From<bool> for LiteralValue {
+    fn from(b: bool) -> Self {
+        LiteralValue::from_bool(b)
+    }
+}
+
+impl From<&str> for LiteralValue {
+    fn from(s: &str) -> Self {
+        LiteralValue::StringValue(s.to_string())
+    }
+}
+
+impl From<String> for LiteralValue {
+    fn from(s: String) -> Self {
+        LiteralValue::StringValue(s)
+    }
+}
+
+impl From<()> for LiteralValue {
+    fn from(_: ()) -> Self {
+        LiteralValue::Nil
+    }
+}
+
+impl<T: Into<LiteralValue>> From<Option<T>> for LiteralValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(LiteralValue::Nil, Into::into)
+    }
+}
+
+impl<T: Into<LiteralValue>> From<Vec<T>> for LiteralValue {
+    fn from(elements: Vec<T>) -> Self {
+        let elements = elements.into_iter().map(Into::into).collect();
+        LiteralValue::list(elements)
+    }
+}
+
+impl<T: Into<LiteralValue>> From<HashMap<String, T>> for LiteralValue {
+    fn from(entries: HashMap<String, T>) -> Self {
+        let entries = entries.into_iter().map(|(k, v)| (k, v.into())).collect();
+        LiteralValue::map(entries)
+    }
+}
+
+fn conversion_error(expected: &str, got: &LiteralValue) -> String {
+    format!("Expected {} but got {}", expected, got.to_string())
+}
+
+impl TryFrom<LiteralValue> for f64 {
+    type Error = String;
+
+    fn try_from(value: LiteralValue) -> Result<Self, String> {
+        match value {
+            LiteralValue::Number(x) => Ok(x),
+            other => Err(conversion_error("a Number", &other)),
+        }
+    }
+}
+
+impl TryFrom<LiteralValue> for bool {
+    type Error = String;
+
+    fn try_from(value: LiteralValue) -> Result<Self, String> {
+        match value {
+            LiteralValue::True => Ok(true),
+            LiteralValue::False => Ok(false),
+            other => Err(conversion_error("a Boolean", &other)),
+        }
+    }
+}
+
+impl TryFrom<LiteralValue> for String {
+    type Error = String;
+
+    fn try_from(value: LiteralValue) -> Result<Self, String> {
+        match value {
+            LiteralValue::StringValue(s) => Ok(s),
+            other => Err(conversion_error("a String", &other)),
+        }
+    }
+}
+
+// Lists and maps are copied out, so later changes in the program do not show
+impl TryFrom<LiteralValue> for Vec<LiteralValue> {
+    type Error = String;
+
+    fn try_from(value: LiteralValue) -> Result<Self, String> {
+        match value {
+            LiteralValue::List(elements) => Ok(elements.borrow().clone()),
+            other => Err(conversion_error("a List", &other)),
+        }
+    }
+}
+
+impl TryFrom<LiteralValue> for HashMap<String, LiteralValue> {
+    type Error = String;
+
+    fn try_from(value: LiteralValue) -> Result<Self, String> {
+        match value {
+            LiteralValue::Map(entries) => Ok(entries.borrow().clone()),
+            other => Err(conversion_error("a Map", &other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip() {
+        assert_eq!(f64::try_from(LiteralValue::from(3)), Ok(3.0));
+        assert_eq!(bool::try_from(LiteralValue::from(true)), Ok(true));
+        assert_eq!(
+            String::try_from(LiteralValue::from("moss")),
+            Ok("moss".to_string())
+        );
+        assert_eq!(LiteralValue::from(None::<f64>), LiteralValue::Nil);
+
+        let list = Vec::<LiteralValue>::try_from(LiteralValue::from(vec![1, 2]));
+        assert_eq!(
+            list,
+            Ok(vec![LiteralValue::Number(1.0), LiteralValue::Number(2.0)])
+        );
+
+        let map = HashMap::from([("a".to_string(), "b")]);
+        let map = HashMap::<String, LiteralValue>::try_from(LiteralValue::from(map)).unwrap();
+        assert_eq!(map["a"], LiteralValue::from("b"));
+    }
+
+    #[test]
+    fn wrong_type_is_an_error() {
+        assert_eq!(
+            f64::try_from(LiteralValue::from("3")),
+            Err("Expected a Number but got \"3\"".to_string())
+        );
+        assert!(bool::try_from(LiteralValue::Nil).is_err());
+    }
+}
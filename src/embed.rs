@@ -0,0 +1,449 @@
+use crate::compiler::Compiler;
+use crate::environment::Environment;
+use crate::error::{ErrorKind, MossError};
+use crate::expr::{call_value, CallableImpl, LiteralValue, NativeFunctionImpl};
+use crate::interpreter::{ControlFlow, Interpreter};
+use crate::output::Output;
+use crate::parser::Parser;
+use crate::process::ProcessPolicy;
+use crate::resolver::{Locals, Resolver};
+use crate::scanner::Scanner;
+use crate::stmt::Stmt;
+use crate::vm::Vm;
+use std::rc::Rc;
+
+/// Which engine executes the resolved program
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    TreeWalker,
+    Vm,
+}
+
+/// A moss program that a host runs code in. Globals defined by one `eval`
+/// stay visible to the next, and to the functions the host calls.
+///
+/// ```
+/// use moss::{Backend, Moss};
+///
+/// let mut moss = Moss::new(Backend::Vm).without_filesystem();
+/// moss.define_native("double", 1, |args| {
+///     Ok((f64::try_from(args[0].clone())? * 2.0).into())
+/// });
+/// moss.eval("fun twice(x) { return double(x); }").unwrap();
+/// assert_eq!(moss.call("twice", vec![21.into()]), Ok(42.into()));
+/// ```
+pub struct Moss {
+    engine: Engine,
+    deny_warnings: bool,
+    warnings: Option<ReportWarnings>,
+}
+
+// Receives the warnings of a source and the source they point into
+type ReportWarnings = Box<dyn FnMut(Vec<MossError>, &str)>;
+
+enum Engine {
+    TreeWalker(Interpreter),
+    Vm(Vm),
+}
+
+impl Moss {
+    pub fn new(backend: Backend) -> Self {
+        let engine = match backend {
+            Backend::TreeWalker => {
+                Engine::TreeWalker(Interpreter::with_env(Environment::new(Locals::default())))
+            }
+            Backend::Vm => Engine::Vm(Vm::new()),
+        };
+        Self {
+            engine,
+            deny_warnings: false,
+            warnings: None,
+        }
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self.engine {
+            Engine::TreeWalker(_) => Backend::TreeWalker,
+            Engine::Vm(_) => Backend::Vm,
+        }
+    }
+
+    /// Sets the file the program was loaded from, which imports are relative to
+    pub fn in_file(self, file: &str) -> Self {
+        self.map_engine(|env| env.in_file(file), |vm| vm.in_file(file))
+    }
+
+    /// Sets which commands the program may run
+    pub fn with_process_policy(self, policy: ProcessPolicy) -> Self {
+        self.map_engine(
+            |env| env.with_process_policy(policy.clone()),
+            |vm| vm.with_process_policy(policy.clone()),
+        )
+    }
+
+    /// Sets where `print` writes, instead of stdout
+    pub fn with_output(self, output: Output) -> Self {
+        self.map_engine(
+            |env| env.with_output(output.clone()),
+            |vm| vm.with_output(output.clone()),
+        )
+    }
+
+    /// Makes the natives that touch the filesystem fail
+    pub fn without_filesystem(self) -> Self {
+        self.map_engine(Environment::without_filesystem, Vm::without_filesystem)
+    }
+
+    /// Makes `eval` fail with the warnings of the source instead of running it
+    pub fn deny_warnings(mut self) -> Self {
+        self.deny_warnings = true;
+        self
+    }
+
+    /// Hands the warnings of each `eval` and its source to `report` before
+    /// the source runs, unless they are denied
+    pub fn with_warnings(mut self, report: impl FnMut(Vec<MossError>, &str) + 'static) -> Self {
+        self.warnings = Some(Box::new(report));
+        self
+    }
+
+    fn map_engine(
+        mut self,
+        tree_walker: impl FnOnce(Environment) -> Environment,
+        vm: impl FnOnce(Vm) -> Vm,
+    ) -> Self {
+        self.engine = match self.engine {
+            Engine::TreeWalker(interpreter) => {
+                Engine::TreeWalker(Interpreter::with_env(tree_walker(interpreter.environment)))
+            }
+            Engine::Vm(v) => Engine::Vm(vm(v)),
+        };
+        self
+    }
+
+    /// The warnings of `source`, such as unused locals or unreachable code,
+    /// without running it. Errors that would stop it from running are
+    /// returned as the error.
+    pub fn check(source: &str) -> Result<Vec<MossError>, Vec<MossError>> {
+        let tokens = Scanner::new(source).scan_tokens()?;
+        let stmts = Parser::new(tokens).parse()?;
+        let (_, warnings) = Resolver::new().resolve_with_warnings(&stmts.iter().collect())?;
+        Ok(warnings)
+    }
+
+    /// Runs `source` in the program. When its last statement is an
+    /// expression, its value is returned.
+    pub fn eval(&mut self, source: &str) -> Result<Option<LiteralValue>, Vec<MossError>> {
+        let tokens = Scanner::new(source).scan_tokens()?;
+        let stmts = Parser::new(tokens).parse()?;
+        let (locals, warnings) = Resolver::new().resolve_with_warnings(&stmts.iter().collect())?;
+        if self.deny_warnings && !warnings.is_empty() {
+            return Err(warnings);
+        }
+        if let (Some(report), false) = (&mut self.warnings, warnings.is_empty()) {
+            report(warnings, source);
+        }
+        let has_value = matches!(stmts.last(), Some(Stmt::Expression { .. }));
+
+        let value = match &mut self.engine {
+            Engine::TreeWalker(interpreter) => {
+                interpreter.resolve(locals);
+                let mut stmts: Vec<&Stmt> = stmts.iter().collect();
+                let last = match stmts.last() {
+                    Some(Stmt::Expression { expression }) if has_value => {
+                        stmts.pop();
+                        Some(expression)
+                    }
+                    _ => None,
+                };
+                // A `return` outside of a function ends the program early
+                match (interpreter.interpret(stmts)?, last) {
+                    (ControlFlow::Return(value), _) => value,
+                    (_, Some(expression)) => expression.evaluate(&interpreter.environment)?,
+                    (_, None) => LiteralValue::Nil,
+                }
+            }
+            // The compiler does its own slot resolution, but the resolver
+            // still reports the static errors
+            Engine::Vm(vm) => {
+                let function = Compiler::new().compile(&stmts.iter().collect())?;
+                vm.interpret(function)?
+            }
+        };
+        Ok(has_value.then_some(value))
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<LiteralValue> {
+        match &self.engine {
+            Engine::TreeWalker(interpreter) => interpreter.environment.get_global(name),
+            Engine::Vm(vm) => vm.module.globals.borrow().get(name).cloned(),
+        }
+    }
+
+    pub fn set_global(&mut self, name: &str, value: impl Into<LiteralValue>) {
+        let value = value.into();
+        match &mut self.engine {
+            Engine::TreeWalker(interpreter) => {
+                interpreter.environment.define(name.to_string(), value)
+            }
+            Engine::Vm(vm) => {
+                vm.module
+                    .globals
+                    .borrow_mut()
+                    .insert(name.to_string(), value);
+            }
+        }
+    }
+
+    /// Lists the globals of the program, one `name: value` per line
+    pub fn dump_globals(&self) -> String {
+        match &self.engine {
+            Engine::TreeWalker(interpreter) => interpreter.environment.dump(0),
+            Engine::Vm(vm) => {
+                let globals = vm.module.globals.borrow();
+                let mut names: Vec<&String> = globals.keys().collect();
+                names.sort();
+                names
+                    .into_iter()
+                    .map(|name| format!("{}: {}\n", name, globals[name].to_string()))
+                    .collect()
+            }
+        }
+    }
+
+    /// Defines a global function implemented in Rust. The program checks that
+    /// it is called with `arity` arguments, and an error it returns is a
+    /// runtime error of the program.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        fun: impl Fn(&[LiteralValue]) -> Result<LiteralValue, String> + 'static,
+    ) {
+        let native = NativeFunctionImpl {
+            name: name.to_string(),
+            arity,
+            fun: Rc::new(fun),
+        };
+        self.set_global(
+            name,
+            LiteralValue::Callable(CallableImpl::NativeFunction(native)),
+        );
+    }
+
+    /// Calls the global function or class `name` with `args`
+    pub fn call(&mut self, name: &str, args: Vec<LiteralValue>) -> Result<LiteralValue, MossError> {
+        let callee = self.get_global(name).ok_or_else(|| {
+            MossError::new(
+                ErrorKind::Runtime,
+                format!("Variable '{}' has not been declared", name),
+                None,
+            )
+        })?;
+
+        match &mut self.engine {
+            Engine::TreeWalker(_) => call_value(callee, args, None),
+            Engine::Vm(vm) => vm.call(callee, args),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Buffer;
+
+    const BACKENDS: [Backend; 2] = [Backend::TreeWalker, Backend::Vm];
+
+    #[test]
+    fn call_functions_defined_by_eval() {
+        for backend in BACKENDS {
+            let mut moss = Moss::new(backend);
+            moss.eval("var count = 0; fun add(a, b) { count += 1; return a + b; }")
+                .unwrap();
+
+            assert_eq!(moss.call("add", vec![1.into(), 2.into()]), Ok(3.into()));
+            assert_eq!(
+                moss.call("add", vec!["a".into(), "b".into()]),
+                Ok("ab".into())
+            );
+            assert_eq!(moss.get_global("count"), Some(2.into()));
+        }
+    }
+
+    #[test]
+    fn eval_returns_the_value_of_a_last_expression() {
+        for backend in BACKENDS {
+            let mut moss = Moss::new(backend);
+            assert_eq!(moss.eval("var a = 20;"), Ok(None));
+            assert_eq!(moss.eval("a += 1; a * 2;"), Ok(Some(42.into())));
+            assert_eq!(moss.eval("fun f() {} f();"), Ok(Some(LiteralValue::Nil)));
+            assert_eq!(moss.eval("if (a) { a; }"), Ok(None));
+        }
+    }
+
+    #[test]
+    fn functions_keep_working_after_later_evals() {
+        for backend in BACKENDS {
+            let mut moss = Moss::new(backend);
+            moss.eval("fun first() { var a = 1; var b = 2; return a + b; }")
+                .unwrap();
+            moss.eval("fun second() { var x = 10; return x; }").unwrap();
+            assert_eq!(moss.eval("first() + second();"), Ok(Some(13.into())));
+        }
+    }
+
+    #[test]
+    fn dropping_a_program_frees_its_cycles() {
+        for backend in BACKENDS {
+            let live = crate::gc::collect_garbage().live;
+            let mut moss = Moss::new(backend);
+            moss.eval("var l = [nil]; l[0] = l; var m = #{}; m.m = m;")
+                .unwrap();
+            moss.eval("class A { init() { this.a = this; } } var a = A();")
+                .unwrap();
+            drop(moss);
+            assert_eq!(crate::gc::collect_garbage().live, live);
+        }
+    }
+
+    #[test]
+    fn warnings_do_not_stop_the_program_unless_denied() {
+        let source = "fun f(unused) { var a = 1; return 2; print a; } var b = f(0);";
+        let warnings = Moss::check(source).unwrap();
+        let messages: Vec<&str> = warnings.iter().map(|w| w.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Parameter 'unused' is never used",
+                "Code after 'return' is unreachable"
+            ]
+        );
+
+        for backend in BACKENDS {
+            let mut moss = Moss::new(backend);
+            moss.eval(source).unwrap();
+            assert_eq!(moss.get_global("b"), Some(2.into()));
+
+            let mut moss = Moss::new(backend).deny_warnings();
+            let errors = moss.eval(source).unwrap_err();
+            assert_eq!(errors.len(), 2);
+            assert_eq!(errors[0].kind, ErrorKind::Warning);
+            assert_eq!(moss.get_global("b"), None);
+        }
+    }
+
+    #[test]
+    fn warnings_are_reported_before_the_program_runs() {
+        for backend in BACKENDS {
+            let buffer = Buffer::default();
+            let warnings = Rc::new(std::cell::RefCell::new(vec![]));
+            let reported = warnings.clone();
+            let mut moss = Moss::new(backend)
+                .with_output(Output::new(buffer.clone()))
+                .with_warnings(move |warnings, _| reported.borrow_mut().extend(warnings));
+
+            moss.eval("var a = 1; { var a = a + 1; print a; } return; print 3;")
+                .unwrap();
+            assert_eq!(buffer.contents(), "2\n");
+            let messages: Vec<String> = warnings
+                .borrow()
+                .iter()
+                .map(|w| w.message.clone())
+                .collect();
+            assert_eq!(
+                messages,
+                vec![
+                    "'a' shadows the variable declared on line 1",
+                    "'a' in its own initializer refers to an outer variable",
+                    "'return' outside of a function ends the program",
+                    "Code after 'return' is unreachable"
+                ]
+            );
+
+            assert_eq!(moss.eval("return 1; 2;"), Ok(Some(1.into())));
+        }
+    }
+
+    #[test]
+    fn natives_and_globals_from_the_host() {
+        for backend in BACKENDS {
+            let mut moss = Moss::new(backend);
+            let factor = 3.0;
+            moss.define_native("scale", 1, move |args| {
+                Ok((f64::try_from(args[0].clone())? * factor).into())
+            });
+            moss.set_global("base", 2);
+
+            moss.eval("var result = scale(base);").unwrap();
+            assert_eq!(moss.get_global("result"), Some(6.into()));
+
+            let errors = moss.eval("scale(\"x\");").unwrap_err();
+            assert_eq!(errors[0].message, "Expected a Number but got \"x\"");
+        }
+    }
+
+    #[test]
+    fn call_errors() {
+        for backend in BACKENDS {
+            let mut moss = Moss::new(backend);
+            moss.eval("fun fail() { return nil + 1; } class Point { init(x) { this.x = x; } }")
+                .unwrap();
+
+            let error = moss.call("missing", vec![]).unwrap_err();
+            assert_eq!(error.message, "Variable 'missing' has not been declared");
+
+            let error = moss.call("fail", vec![1.into()]).unwrap_err();
+            assert_eq!(
+                error.message,
+                "Callable fail expected 0 arguments but got 1"
+            );
+
+            let error = moss.call("fail", vec![]).unwrap_err();
+            assert!(error.span.is_some());
+
+            // The program is still usable after an error
+            let point = moss.call("Point", vec![5.into()]).unwrap();
+            moss.set_global("p", point);
+            moss.eval("var x = p.x;").unwrap();
+            assert_eq!(moss.get_global("x"), Some(5.into()));
+        }
+    }
+
+    #[test]
+    fn traces_name_natives_and_classes() {
+        let source = "class C { init(s) { this.s = upper(s); } }\n\
+                      fun g() { return C(1); }\n\
+                      g();";
+        for backend in BACKENDS {
+            let errors = Moss::new(backend).eval(source).unwrap_err();
+            let functions: Vec<&str> = errors[0]
+                .trace
+                .iter()
+                .map(|frame| frame.function.as_str())
+                .collect();
+            assert_eq!(functions, vec!["upper", "C", "g"], "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn print_goes_to_the_output() {
+        let dir = std::env::temp_dir().join(format!("moss-output-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("loud.moss"), "print \"loading\";").unwrap();
+        let main = dir.join("main.moss").display().to_string();
+
+        for backend in BACKENDS {
+            let buffer = Buffer::default();
+            let mut moss = Moss::new(backend)
+                .in_file(&main)
+                .with_output(Output::new(buffer.clone()));
+            moss.eval("import loud from \"loud.moss\"; fun show(x) { print x; }")
+                .unwrap();
+            moss.call("show", vec![vec![1, 2].into()]).unwrap();
+
+            assert_eq!(buffer.contents(), "\"loading\"\n[1, 2]\n");
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
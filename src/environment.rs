@@ -1,155 +1,424 @@
+use crate::error::{Frame, Span, SCRIPT};
 use crate::expr::{CallableImpl, LiteralValue, NativeFunctionImpl};
+use crate::gc::{self, Trace};
+use crate::module::Modules;
+use crate::output::Output;
+use crate::process::ProcessPolicy;
+use crate::resolver::{Capture, Locals, Slot};
+use crate::stdlib;
+use crate::vm::FRAMES_MAX;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
+/// The variables a piece of code can see. Locals live in scopes of slots, in
+/// the order the resolver numbered them, and scopes point at the scope they
+/// are nested in. A function only sees its own scopes and the variables it
+/// captured when it was created; globals are looked up by name. Cloning an
+/// environment only copies three pointers.
 #[derive(Clone)]
 pub struct Environment {
-    pub values: Rc<RefCell<HashMap<String, LiteralValue>>>,
-    locals: Rc<RefCell<HashMap<usize, usize>>>,
-    pub enclosing: Option<Box<Environment>>,
+    scope: Option<Rc<Scope>>,
+    upvalues: Rc<Vec<Variable>>,
+    context: Rc<Context>,
 }
 
-fn clock_impl(_args: &Vec<LiteralValue>) -> LiteralValue {
+/// A local variable. Closures that capture it share it with its scope.
+pub type Variable = Rc<RefCell<LiteralValue>>;
+
+struct Scope {
+    values: RefCell<Vec<Variable>>,
+    enclosing: Option<Rc<Scope>>,
+}
+
+// What every environment of a module shares. The calls in progress, the
+// loaded modules, the capabilities and the output are also shared with the
+// modules it imports.
+#[derive(Clone)]
+struct Context {
+    globals: Rc<RefCell<HashMap<String, LiteralValue>>>,
+    // Where the code run last finds its locals. Functions keep the ones of
+    // the code that defined them.
+    locals: Rc<Locals>,
+    calls: Rc<RefCell<Vec<Frame>>>,
+    modules: Rc<Modules>,
+    process: Rc<ProcessPolicy>,
+    filesystem: bool,
+    output: Output,
+    file: Rc<str>,
+}
+
+fn clock_impl(_args: &[LiteralValue]) -> Result<LiteralValue, String> {
     let now = std::time::SystemTime::now()
         .duration_since(std::time::SystemTime::UNIX_EPOCH)
         .expect("Could not get system time")
         .as_millis();
 
-    LiteralValue::Number(now as f64 / 1000.0)
+    Ok(LiteralValue::Number(now as f64 / 1000.0))
 }
 
-fn get_globals() -> Rc<RefCell<HashMap<String, LiteralValue>>> {
-    let mut env = HashMap::new();
+// Runs the cycle collector and returns the counts of the heap as a map
+fn gc_impl(_args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let stats = gc::collect_garbage();
+    let counts = [
+        ("allocated", stats.allocated),
+        ("live", stats.live),
+        ("collections", stats.collections),
+        ("freed", stats.freed),
+    ];
+    Ok(LiteralValue::map(
+        counts
+            .into_iter()
+            .map(|(name, count)| (name.to_string(), LiteralValue::Number(count as f64)))
+            .collect(),
+    ))
+}
+
+pub fn define_native(
+    env: &mut HashMap<String, LiteralValue>,
+    name: &str,
+    arity: usize,
+    fun: fn(&[LiteralValue]) -> Result<LiteralValue, String>,
+) {
     let fun_impl = NativeFunctionImpl {
-        name: "clock".to_string(),
-        arity: 0,
-        fun: Rc::new(clock_impl),
+        name: name.to_string(),
+        arity,
+        fun: Rc::new(fun),
     };
     let callable_impl = CallableImpl::NativeFunction(fun_impl);
-    env.insert("clock".to_string(), LiteralValue::Callable(callable_impl));
+    env.insert(name.to_string(), LiteralValue::Callable(callable_impl));
+}
 
-    Rc::new(RefCell::new(env))
+pub fn get_globals() -> Rc<RefCell<HashMap<String, LiteralValue>>> {
+    let mut env = HashMap::new();
+    define_native(&mut env, "clock", 0, clock_impl);
+    define_native(&mut env, "gc", 0, gc_impl);
+    stdlib::collections::define(&mut env);
+    stdlib::io::define(&mut env);
+    stdlib::math::define(&mut env);
+    stdlib::string::define(&mut env);
+
+    let globals = Rc::new(RefCell::new(env));
+    gc::track(&globals);
+    globals
 }
 
 impl Environment {
-    pub fn new(locals: HashMap<usize, usize>) -> Self {
+    pub fn new(locals: Locals) -> Self {
+        let modules = Rc::new(Modules::default());
+        gc::track(&modules);
+        Self::in_context(Context {
+            globals: get_globals(),
+            locals: Rc::new(locals),
+            calls: Rc::new(RefCell::new(vec![])),
+            modules,
+            process: Rc::new(ProcessPolicy::default()),
+            filesystem: true,
+            output: Output::default(),
+            file: SCRIPT.into(),
+        })
+    }
+
+    // The top level of a context, which functions keep alive and so is
+    // tracked by the cycle collector
+    fn in_context(context: Context) -> Self {
+        let context = Rc::new(context);
+        gc::track(&context);
         Self {
-            values: get_globals(),
-            locals: Rc::new(RefCell::new(locals)),
-            enclosing: None,
+            scope: None,
+            upvalues: Rc::default(),
+            context,
         }
     }
 
-    pub fn resolve(&self, locals: HashMap<usize, usize>) {
-        // self.locals = locals --! Bad because it wont update enclosing
-        for (key, val) in locals.iter() {
-            self.locals.borrow_mut().insert(*key, *val);
+    // Changes the context of a program that is being set up
+    fn configure(mut self, f: impl FnOnce(&mut Context)) -> Self {
+        f(Rc::make_mut(&mut self.context));
+        // A context that was shared has been copied
+        gc::track(&self.context);
+        self
+    }
+
+    /// Sets the file the program was loaded from
+    pub fn in_file(self, file: &str) -> Self {
+        self.configure(|context| context.file = file.into())
+    }
+
+    /// Fresh globals for a module imported by this program
+    pub fn for_module(&self, file: &str) -> Environment {
+        let globals = get_globals();
+        if !self.context.filesystem {
+            stdlib::io::disable_filesystem(&mut globals.borrow_mut());
         }
+
+        Self::in_context(Context {
+            globals,
+            locals: Rc::new(Locals::default()),
+            file: file.into(),
+            ..(*self.context).clone()
+        })
     }
 
+    /// Sets which commands the program may run
+    pub fn with_process_policy(self, policy: ProcessPolicy) -> Self {
+        self.configure(|context| context.process = Rc::new(policy))
+    }
+
+    pub fn process_policy(&self) -> Rc<ProcessPolicy> {
+        self.context.process.clone()
+    }
+
+    /// Sets where `print` writes
+    pub fn with_output(self, output: Output) -> Self {
+        self.configure(|context| context.output = output)
+    }
+
+    pub fn output(&self) -> &Output {
+        &self.context.output
+    }
+
+    /// Makes the natives that touch the filesystem fail
+    pub fn without_filesystem(self) -> Self {
+        stdlib::io::disable_filesystem(&mut self.context.globals.borrow_mut());
+        self.configure(|context| context.filesystem = false)
+    }
+
+    pub fn file(&self) -> &str {
+        &self.context.file
+    }
+
+    pub fn modules(&self) -> Rc<Modules> {
+        self.context.modules.clone()
+    }
+
+    /// The same globals, for running code resolved to `locals`. Each parse
+    /// numbers its expressions from zero, so the functions defined by earlier
+    /// code keep their own.
+    pub fn with_locals(&self, locals: Locals) -> Environment {
+        let context = Context {
+            locals: Rc::new(locals),
+            ..(*self.context).clone()
+        };
+        Self {
+            scope: self.scope.clone(),
+            upvalues: self.upvalues.clone(),
+            ..Self::in_context(context)
+        }
+    }
+
+    /// A new, empty scope inside this one
     pub fn enclose(&self) -> Environment {
         Self {
-            values: Rc::new(RefCell::new(HashMap::new())),
-            locals: self.locals.clone(),
-            enclosing: Some(Box::new(self.clone())),
+            scope: Some(Rc::new(Scope {
+                values: RefCell::new(vec![]),
+                enclosing: self.scope.clone(),
+            })),
+            upvalues: self.upvalues.clone(),
+            context: self.context.clone(),
         }
     }
 
-    pub fn define(&self, name: String, value: LiteralValue) {
-        self.values.borrow_mut().insert(name, value);
+    /// The environment a function created here runs in: the globals and the
+    /// variables it captures. `key` is the offset of its name.
+    pub fn capture(&self, key: usize) -> Environment {
+        let captures = self.context.locals.captures.get(&key);
+        let upvalues = captures
+            .into_iter()
+            .flatten()
+            .map(|capture| match *capture {
+                Capture::Local { depth, slot } => self.variable(depth, slot),
+                Capture::Upvalue(index) => self.upvalues.get(index).cloned(),
+            })
+            // Only a resolver bug leaves a capture without a variable
+            .map(|variable| variable.expect("Captured variable is missing"))
+            // Only captured variables can be part of a cycle, and they are
+            // tracked the first time they are
+            .inspect(gc::track)
+            .collect();
+        let upvalues = Rc::new(upvalues);
+        gc::track(&upvalues);
+
+        Self {
+            scope: None,
+            upvalues,
+            context: self.context.clone(),
+        }
     }
 
-    pub fn get(&self, name: &str, expr_id: usize) -> Option<LiteralValue> {
-        let distance = self.locals.borrow().get(&expr_id).cloned();
-        self.get_internal(name, distance)
+    /// Records a call, which fails once calls nest as deep as the vm allows.
+    /// The call is recorded either way, for the trace of the error.
+    pub fn push_call(&self, function: &str, call_site: Span) -> Result<(), String> {
+        let mut calls = self.context.calls.borrow_mut();
+        calls.push(Frame {
+            function: function.to_string(),
+            call_site,
+            file: self.context.file.to_string(),
+        });
+        if calls.len() >= FRAMES_MAX {
+            return Err("Stack overflow".to_string());
+        }
+        Ok(())
     }
 
-    pub fn get_this_instance(&self, super_id: usize) -> Option<LiteralValue> {
-        let distance = self
-            .locals
-            .borrow()
-            .get(&super_id)
-            .cloned()
-            .expect("Could not find 'this' even though 'super' was defined");
-        self.get_internal("this", Some(distance - 1))
+    pub fn pop_call(&self) {
+        self.context.calls.borrow_mut().pop();
     }
 
-    pub fn get_distance(&self, expr_id: usize) -> Option<usize> {
-        self.locals.borrow().get(&expr_id).cloned()
+    /// The calls in progress, innermost first
+    pub fn call_stack(&self) -> Vec<Frame> {
+        self.context.calls.borrow().iter().rev().cloned().collect()
     }
 
-    fn get_internal(&self, name: &str, distance: Option<usize>) -> Option<LiteralValue> {
-        if let None = distance {
-            match &self.enclosing {
-                None => self.values.borrow().get(name).cloned(),
-                Some(env) => env.get_internal(name, distance),
+    /// Defines a variable in the innermost scope, in the next slot, or a
+    /// global at the top level. The name only matters for globals.
+    pub fn define(&self, name: String, value: LiteralValue) {
+        match &self.scope {
+            Some(scope) => scope.values.borrow_mut().push(Rc::new(RefCell::new(value))),
+            None => {
+                self.context.globals.borrow_mut().insert(name, value);
             }
-        } else {
-            let distance = distance.unwrap();
-            if distance == 0 {
-                self.values.borrow().get(name).cloned()
-            } else {
-                match &self.enclosing {
-                    None => panic!("Tried to resolve a variable that was defined deeper than the current environment depth"),
-                    Some(env) => {
-                        assert!(distance > 0);
-                        env.get_internal(name, Some(distance - 1))
-                    }
+        }
+    }
+
+    /// Sets the variable defined last, for a declaration that refers to
+    /// itself and so has to exist before its value does
+    pub fn define_last(&self, name: &str, value: LiteralValue) {
+        match &self.scope {
+            Some(scope) => {
+                if let Some(variable) = scope.values.borrow().last() {
+                    *variable.borrow_mut() = value;
                 }
             }
+            None => {
+                self.context
+                    .globals
+                    .borrow_mut()
+                    .insert(name.to_string(), value);
+            }
         }
     }
 
+    pub fn get(&self, name: &str, expr_id: usize) -> Option<LiteralValue> {
+        match self.get_slot(expr_id) {
+            Some(slot) => Some(self.lookup(slot)?.borrow().clone()),
+            None => self.get_global(name),
+        }
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<LiteralValue> {
+        self.context.globals.borrow().get(name).cloned()
+    }
+
+    /// The instance a `super` expression calls its method on
+    pub fn get_this_instance(&self, super_id: usize) -> Option<LiteralValue> {
+        let slot = *self.context.locals.receivers.get(&super_id)?;
+        let this = self.lookup(slot)?.borrow().clone();
+        Some(this)
+    }
+
+    pub fn get_slot(&self, expr_id: usize) -> Option<Slot> {
+        self.context.locals.variables.get(&expr_id).cloned()
+    }
+
+    fn lookup(&self, slot: Slot) -> Option<Variable> {
+        match slot {
+            Slot::Local { depth, slot } => self.variable(depth, slot),
+            Slot::Upvalue(index) => self.upvalues.get(index).cloned(),
+        }
+    }
+
+    // A local resolved deeper than the current scope depth is not found
+    fn variable(&self, depth: usize, slot: usize) -> Option<Variable> {
+        let mut scope = self.scope.as_ref()?;
+        for _ in 0..depth {
+            scope = scope.enclosing.as_ref()?;
+        }
+        let variable = scope.values.borrow().get(slot).cloned();
+        variable
+    }
+
+    /// Assigns an existing global
     pub fn assign_global(&self, name: &str, value: LiteralValue) -> bool {
-        self.assign_internal(name, value, None)
+        match self.context.globals.borrow_mut().get_mut(name) {
+            Some(global) => {
+                *global = value;
+                true
+            }
+            None => false,
+        }
     }
 
     pub fn assign(&self, name: &str, value: LiteralValue, expr_id: usize) -> bool {
         // ! Important that this ID matches with the resolver
-        let distance = self.locals.borrow().get(&expr_id).cloned();
-        self.assign_internal(name, value, distance)
-    }
-
-    fn assign_internal(&self, name: &str, value: LiteralValue, distance: Option<usize>) -> bool {
-        if let None = distance {
-            match &self.enclosing {
-                Some(env) => env.assign_internal(name, value, distance),
-                None => match self.values.borrow_mut().insert(name.to_string(), value) {
-                    Some(_) => true,
-                    None => false,
-                },
-            }
-        } else {
-            let distance = distance.unwrap();
-            if distance == 0 {
-                self.values.borrow_mut().insert(name.to_string(), value);
-                true
-            } else {
-                match &self.enclosing {
-                    None => panic!("Tried to define a variable in a too deep level"),
-                    Some(env) => env.assign_internal(name, value, Some(distance - 1)),
-                };
-                true
-            }
+        match self.get_slot(expr_id) {
+            Some(slot) => match self.lookup(slot) {
+                Some(variable) => {
+                    *variable.borrow_mut() = value;
+                    true
+                }
+                None => false,
+            },
+            None => self.assign_global(name, value),
         }
     }
 
-    #[allow(dead_code)]
+    /// The slots of each scope, innermost first, the captured variables and
+    /// then the globals
     pub fn dump(&self, indent: usize) -> String {
         let mut result = String::new();
-        for (key, val) in self.values.borrow().iter() {
-            for _ in 0..indent {
-                result.push_str(" ");
+        let mut indent = indent;
+        let mut scope = self.scope.as_ref();
+        while let Some(current) = scope {
+            for (slot, value) in current.values.borrow().iter().enumerate() {
+                let value = value.borrow().to_string();
+                result.push_str(&format!("{}#{}: {}\n", " ".repeat(indent), slot, value));
             }
-            result.push_str(&format!("{}: {:?}\n", key, val));
+            indent += 2;
+            scope = current.enclosing.as_ref();
+        }
+        for (index, value) in self.upvalues.iter().enumerate() {
+            let value = value.borrow().to_string();
+            result.push_str(&format!("{}^{}: {}\n", " ".repeat(indent), index, value));
         }
-        if let Some(env) = &self.enclosing {
-            result.push_str(&env.dump(indent + 2));
+
+        let globals = self.context.globals.borrow();
+        let mut keys: Vec<&String> = globals.keys().collect();
+        keys.sort();
+        for key in keys {
+            let value = globals[key].to_string();
+            result.push_str(&format!("{}{}: {}\n", " ".repeat(indent), key, value));
         }
 
         result
     }
+
+    /// Visits what a function that runs here keeps alive: the variables it
+    /// captured and its context. Scopes only live while code runs in them.
+    pub fn trace(&self, visit: &mut dyn FnMut(*const ())) {
+        visit(gc::address(&self.upvalues));
+        visit(gc::address(&self.context));
+    }
+}
+
+impl Trace for Vec<Variable> {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        self.iter()
+            .for_each(|variable| visit(gc::address(variable)));
+        true
+    }
+
+    // The variables are cleared themselves if nothing else refers to them
+    fn clear(&self) {}
+}
+
+impl Trace for Context {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        visit(gc::address(&self.globals));
+        visit(gc::address(&self.modules));
+        true
+    }
+
+    fn clear(&self) {}
 }
 
 #[cfg(test)]
@@ -157,6 +426,64 @@ mod tests {
     use super::*;
     #[test]
     fn try_init() {
-        let _environment = Environment::new(HashMap::new());
+        let _environment = Environment::new(Locals::default());
+    }
+
+    fn locals(variables: Vec<(usize, Slot)>) -> Locals {
+        Locals {
+            variables: variables.into_iter().collect(),
+            ..Locals::default()
+        }
+    }
+
+    #[test]
+    fn too_deep_distance_is_not_found() {
+        let environment = Environment::new(locals(vec![(0, Slot::Local { depth: 2, slot: 0 })]));
+        let inner = environment.enclose();
+        inner.define("x".to_string(), LiteralValue::Nil);
+
+        assert!(inner.get("x", 0).is_none());
+        assert!(!inner.assign("x", LiteralValue::True, 0));
+        assert!(inner.get_this_instance(0).is_none());
+    }
+
+    #[test]
+    fn locals_are_found_by_slot() {
+        let outer = Environment::new(locals(vec![
+            (0, Slot::Local { depth: 0, slot: 1 }),
+            (1, Slot::Local { depth: 1, slot: 0 }),
+            (2, Slot::Local { depth: 0, slot: 5 }),
+        ]))
+        .enclose();
+        outer.define("a".to_string(), LiteralValue::Number(1.0));
+        let inner = outer.enclose();
+        inner.define("b".to_string(), LiteralValue::Number(2.0));
+        inner.define("c".to_string(), LiteralValue::Number(3.0));
+
+        assert_eq!(inner.get("c", 0), Some(LiteralValue::Number(3.0)));
+        assert_eq!(inner.get("a", 1), Some(LiteralValue::Number(1.0)));
+        assert!(inner.assign("a", LiteralValue::Nil, 1));
+        assert_eq!(inner.get("a", 1), Some(LiteralValue::Nil));
+        // A slot that was never defined
+        assert!(inner.get("d", 2).is_none());
+        // Anything unresolved is a global
+        assert!(inner.get("clock", 3).is_some());
+    }
+
+    #[test]
+    fn captured_variables_are_shared() {
+        let mut locals = locals(vec![(0, Slot::Upvalue(0))]);
+        locals
+            .captures
+            .insert(7, vec![Capture::Local { depth: 1, slot: 0 }]);
+        let outer = Environment::new(locals).enclose();
+        outer.define("count".to_string(), LiteralValue::Number(0.0));
+        outer.define("unused".to_string(), LiteralValue::Nil);
+
+        let closure = outer.enclose().capture(7);
+        assert_eq!(closure.upvalues.len(), 1);
+        assert!(closure.assign("count", LiteralValue::Number(1.0), 0));
+        let count = outer.variable(0, 0).unwrap();
+        assert_eq!(*count.borrow(), LiteralValue::Number(1.0));
     }
 }
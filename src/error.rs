@@ -0,0 +1,298 @@
+use crate::scanner::Token;
+use std::fmt;
+
+/// What diagnostics call a script that didn't come from a file
+pub const SCRIPT: &str = "<script>";
+
+/// The stage of running a script that produced an error
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Io,
+    Scan,
+    Parse,
+    Resolve,
+    Compile,
+    Runtime,
+    // Found by the resolver, only an error when warnings are denied
+    Warning,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ErrorKind::Io => "io",
+            ErrorKind::Scan => "scan",
+            ErrorKind::Parse => "parse",
+            ErrorKind::Resolve => "resolve",
+            ErrorKind::Compile => "compile",
+            ErrorKind::Runtime => "runtime",
+            ErrorKind::Warning => "warning",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// A region of the source. `offset` is the byte offset of its start, `len` and
+/// `column` are counted in characters, `line` and `column` start at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub offset: usize,
+    pub len: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A call that was running when an error happened: the function called and
+/// where it was called from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub function: String,
+    pub call_site: Span,
+    // The file of the call site
+    pub file: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MossError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub span: Option<Span>,
+    pub file: Option<String>,
+    // The calls the error unwound through, innermost first
+    pub trace: Vec<Frame>,
+}
+
+impl MossError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>, span: Option<Span>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            span,
+            file: None,
+            trace: vec![],
+        }
+    }
+
+    pub fn scan(message: impl Into<String>, span: Span) -> Self {
+        Self::new(ErrorKind::Scan, message, Some(span))
+    }
+
+    pub fn parse(message: impl Into<String>, token: &Token) -> Self {
+        Self::new(ErrorKind::Parse, message, Some(token.span()))
+    }
+
+    pub fn resolve(message: impl Into<String>, token: &Token) -> Self {
+        Self::new(ErrorKind::Resolve, message, Some(token.span()))
+    }
+
+    pub fn runtime(message: impl Into<String>, token: &Token) -> Self {
+        Self::new(ErrorKind::Runtime, message, Some(token.span()))
+    }
+
+    /// Records the file the error happened in, unless one is already known
+    pub fn in_file(mut self, file: &str) -> Self {
+        if self.file.is_none() {
+            self.file = Some(file.to_string());
+        }
+        self
+    }
+
+    /// Records the call stack at the point of the error. Only the innermost
+    /// call sees the whole stack, so an error keeps the first trace it gets.
+    pub fn with_trace(mut self, trace: Vec<Frame>) -> Self {
+        if self.trace.is_empty() {
+            self.trace = trace;
+        }
+        self
+    }
+
+    /// The location of the error followed by the offending source line, with
+    /// the span underlined by carets:
+    ///
+    /// ```text
+    ///  --> script.moss:3:9
+    ///   |
+    /// 3 | print a.test;
+    ///   |         ^^^^
+    /// ```
+    pub fn snippet(&self, source: &str) -> Option<String> {
+        let span = self.span?;
+        let text = source.lines().nth(span.line.checked_sub(1)?)?;
+        let file = self.file.as_deref().unwrap_or(SCRIPT);
+
+        let number = span.line.to_string();
+        let gutter = " ".repeat(number.len());
+        // Spans that run past the end of the line (e.g. multi-line strings) are cut off there
+        let column = span.column.max(1);
+        let available = text.chars().count().saturating_sub(column - 1).max(1);
+        let carets = "^".repeat(span.len.clamp(1, available));
+
+        Some(format!(
+            "{gutter}--> {file}:{}:{column}\n{gutter} |\n{number} | {text}\n{gutter} | {}{carets}\n",
+            span.line,
+            " ".repeat(column - 1),
+        ))
+    }
+
+    /// The calls the error unwound through, most recent first:
+    ///
+    /// ```text
+    /// stack trace:
+    ///   in area, called at script.moss:7:11
+    ///   in Square, called at script.moss:10:14
+    /// ```
+    pub fn traceback(&self) -> Option<String> {
+        if self.trace.is_empty() {
+            return None;
+        }
+
+        // Runs of the same call, as deep recursion makes, print once
+        let mut result = "stack trace:\n".to_string();
+        let mut frames = self.trace.iter().peekable();
+        while let Some(frame) = frames.next() {
+            result.push_str(&format!(
+                "  in {}, called at {}:{}:{}\n",
+                frame.function, frame.file, frame.call_site.line, frame.call_site.column
+            ));
+            let mut repeated = 0;
+            while frames.next_if_eq(&frame).is_some() {
+                repeated += 1;
+            }
+            if repeated > 0 {
+                result.push_str(&format!("  ... repeated {} more times\n", repeated));
+            }
+        }
+        Some(result)
+    }
+
+    /// The full diagnostic: the kind and message, then the source snippet if
+    /// the error has a location and the stack trace if it happened in a call
+    pub fn render(&self, source: &str) -> String {
+        let mut result = match self.kind {
+            ErrorKind::Warning => format!("warning: {}\n", self.message),
+            kind => format!("{} error: {}\n", kind, self.message),
+        };
+        if let Some(snippet) = self.snippet(source) {
+            result.push_str(&snippet);
+        }
+        if let Some(traceback) = self.traceback() {
+            result.push_str(&traceback);
+        }
+        result
+    }
+}
+
+impl fmt::Display for MossError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+// Lets `?` turn a single error into the list returned by the scanner and parser
+impl From<MossError> for Vec<MossError> {
+    fn from(error: MossError) -> Self {
+        vec![error]
+    }
+}
+
+/// Attaches the location of a token to the plain `String` errors returned by
+/// the value helpers shared between the interpreter and the vm
+pub trait ErrorAt<T> {
+    fn at(self, token: &Token) -> Result<T, MossError>;
+}
+
+impl<T> ErrorAt<T> for Result<T, String> {
+    fn at(self, token: &Token) -> Result<T, MossError> {
+        self.map_err(|message| MossError::runtime(message, token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snippet_underlines_span() {
+        let error = MossError::new(
+            ErrorKind::Runtime,
+            "No field named test on this instance",
+            Some(Span {
+                offset: 20,
+                len: 4,
+                line: 2,
+                column: 9,
+            }),
+        )
+        .in_file("bagel.moss");
+
+        assert_eq!(
+            error.render("var a = Bagel();\nprint a.test;\n"),
+            "runtime error: No field named test on this instance\n \
+             --> bagel.moss:2:9\n  |\n2 | print a.test;\n  |         ^^^^\n"
+        );
+    }
+
+    #[test]
+    fn traceback_lists_innermost_call_first() {
+        let call_site = |line, column| Span {
+            offset: 0,
+            len: 1,
+            line,
+            column,
+        };
+        let error = MossError::new(ErrorKind::Runtime, "Stack overflow", None).with_trace(vec![
+            Frame {
+                function: "inner".to_string(),
+                call_site: call_site(2, 20),
+                file: "lib.moss".to_string(),
+            },
+            Frame {
+                function: "outer".to_string(),
+                call_site: call_site(4, 6),
+                file: SCRIPT.to_string(),
+            },
+        ]);
+
+        assert_eq!(
+            error.render(""),
+            "runtime error: Stack overflow\nstack trace:\n  \
+             in inner, called at lib.moss:2:20\n  \
+             in outer, called at <script>:4:6\n"
+        );
+        // The innermost call records the trace, the callers keep it
+        let error = error.with_trace(vec![]);
+        assert_eq!(error.trace.len(), 2);
+    }
+
+    #[test]
+    fn traceback_collapses_repeated_calls() {
+        let frame = |function: &str, line| Frame {
+            function: function.to_string(),
+            call_site: Span {
+                offset: 0,
+                len: 1,
+                line,
+                column: 1,
+            },
+            file: SCRIPT.to_string(),
+        };
+        let mut trace = vec![frame("f", 2); 4];
+        trace.push(frame("f", 5));
+        let error = MossError::new(ErrorKind::Runtime, "Stack overflow", None).with_trace(trace);
+
+        assert_eq!(
+            error.traceback().unwrap(),
+            "stack trace:\n  \
+             in f, called at <script>:2:1\n  \
+             ... repeated 3 more times\n  \
+             in f, called at <script>:5:1\n"
+        );
+    }
+
+    #[test]
+    fn snippet_without_span() {
+        let error = MossError::new(ErrorKind::Io, "No such file", None);
+        assert_eq!(error.snippet("print 1;"), None);
+        assert_eq!(error.render("print 1;"), "io error: No such file\n");
+    }
+}
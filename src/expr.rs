@@ -1,34 +1,151 @@
 use crate::environment::Environment;
-use crate::interpreter::Interpreter;
+use crate::error::{ErrorAt, ErrorKind, MossError};
+use crate::gc::{self, Trace};
+use crate::interpreter::{ControlFlow, Interpreter};
 use crate::scanner;
 use crate::scanner::{Token, TokenType};
+use crate::vm::Closure;
 use std::cell::RefCell;
 use std::cmp::{Eq, PartialEq};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 
+// Calls run on a new piece of stack when less than `STACK_RED_ZONE` is left,
+// so that deep recursion ends in a `Stack overflow` error instead of
+// overflowing the stack of the host
+const STACK_RED_ZONE: usize = 256 * 1024;
+const STACK_SEGMENT: usize = 4 * 1024 * 1024;
+
 #[derive(Clone)]
 pub enum CallableImpl {
-    LoxFunction(LoxFunctionImpl),
+    LoxFunction(Rc<LoxFunctionImpl>),
     NativeFunction(NativeFunctionImpl),
+    Closure(Rc<Closure>),
+    BoundMethod(BoundMethodImpl),
 }
 use CallableImpl::*;
 
-#[derive(Clone)]
+impl CallableImpl {
+    pub fn name(&self) -> &str {
+        match self {
+            LoxFunction(fun) => &fun.name,
+            NativeFunction(fun) => &fun.name,
+            Closure(closure) => &closure.function.name,
+            BoundMethod(bound) => bound.method.name(),
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        match self {
+            LoxFunction(fun) => fun.arity,
+            NativeFunction(fun) => fun.arity,
+            Closure(closure) => closure.function.arity,
+            BoundMethod(bound) => bound.method.arity(),
+        }
+    }
+
+    /// A new function of the tree-walker, tracked by the cycle collector
+    pub fn lox_function(fun: LoxFunctionImpl) -> CallableImpl {
+        let fun = Rc::new(fun);
+        gc::track(&fun);
+        LoxFunction(fun)
+    }
+
+    /// Binds a method to `instance` so that `this` refers to it when called
+    pub fn bind(&self, instance: LiteralValue) -> CallableImpl {
+        match self {
+            LoxFunction(_) | Closure(_) => BoundMethod(BoundMethodImpl {
+                receiver: Box::new(instance),
+                method: Box::new(self.clone()),
+            }),
+            other => other.clone(),
+        }
+    }
+
+    // Natives are opaque: whatever a host closure captured stays alive
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) {
+        match self {
+            LoxFunction(fun) => visit(gc::address(fun)),
+            NativeFunction(_) => (),
+            Closure(closure) => visit(gc::address(closure)),
+            BoundMethod(bound) => {
+                bound.receiver.trace(visit);
+                bound.method.trace(visit);
+            }
+        }
+    }
+}
+
+// Functions are the same only if they were created by the same declaration
+// being run once. A bound method is the same as another of the same method
+// bound to the same instance.
+impl PartialEq for CallableImpl {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (LoxFunction(x), LoxFunction(y)) => Rc::ptr_eq(x, y),
+            (NativeFunction(x), NativeFunction(y)) => Rc::ptr_eq(&x.fun, &y.fun),
+            (Closure(x), Closure(y)) => Rc::ptr_eq(x, y),
+            (BoundMethod(x), BoundMethod(y)) => x.receiver == y.receiver && x.method == y.method,
+            _ => false,
+        }
+    }
+}
+
+#[allow(clippy::vec_box)]
 pub struct LoxFunctionImpl {
     pub name: String,
     pub arity: usize,
-    pub parent_env: Environment,
+    // The globals and the variables the function captured where it was created
+    pub env: Environment,
     pub params: Vec<Token>,
     pub body: Vec<Box<Stmt>>,
+    // An `init` method, which returns its instance however it is called
+    pub initializer: bool,
 }
 
+// A method with its receiver attached, which `this` refers to when it runs
+#[derive(Clone)]
+pub struct BoundMethodImpl {
+    pub receiver: Box<LiteralValue>,
+    // A function of the tree-walker or a closure of the bytecode backend
+    pub method: Box<CallableImpl>,
+}
+
+// Natives report bad arguments with an error message rather than panicking
+pub type NativeFn = Rc<dyn Fn(&[LiteralValue]) -> Result<LiteralValue, String>>;
+
 #[derive(Clone)]
 pub struct NativeFunctionImpl {
     pub name: String,
     pub arity: usize,
-    pub fun: Rc<dyn Fn(&Vec<LiteralValue>) -> LiteralValue>,
+    pub fun: NativeFn,
+}
+
+#[derive(Clone)]
+pub struct LoxClassImpl {
+    pub name: String,
+    pub methods: HashMap<String, CallableImpl>,
+    pub superclass: Option<Rc<LoxClassImpl>>,
+}
+
+impl LoxClassImpl {
+    /// The method called `name` of the class or of the closest superclass
+    /// that has one
+    pub fn find_method(&self, name: &str) -> Option<CallableImpl> {
+        let mut class = self;
+        loop {
+            if let Some(method) = class.methods.get(name) {
+                return Some(method.clone());
+            }
+            class = class.superclass.as_ref()?;
+        }
+    }
+}
+
+pub struct LoxInstanceImpl {
+    pub class: Rc<LoxClassImpl>,
+    pub fields: RefCell<HashMap<String, LiteralValue>>,
 }
 
 #[derive(Clone)]
@@ -39,16 +156,13 @@ pub enum LiteralValue {
     False,
     Nil,
     Callable(CallableImpl),
-    LoxClass {
-        name: String,
-        methods: HashMap<String, LoxFunctionImpl>,
-        superclass: Option<Box<LiteralValue>>,
-        //methods: Vec<(String, LiteralValue)>, // TODO Could also add static fields?
-    },
-    LoxInstance {
-        class: Box<LiteralValue>,
-        fields: Rc<RefCell<Vec<(String, LiteralValue)>>>,
-    },
+    // Classes and instances are shared like lists and maps
+    LoxClass(Rc<LoxClassImpl>),
+    LoxInstance(Rc<LoxInstanceImpl>),
+    // Lists are shared: copies of the value alias the same elements
+    List(Rc<RefCell<Vec<LiteralValue>>>),
+    // Maps are shared in the same way as lists
+    Map(Rc<RefCell<HashMap<String, LiteralValue>>>),
 }
 use LiteralValue::*;
 
@@ -60,25 +174,45 @@ impl std::fmt::Debug for LiteralValue {
 
 impl PartialEq for LiteralValue {
     fn eq(&self, other: &Self) -> bool {
+        self.equals(other, &mut HashSet::new())
+    }
+}
+
+impl LiteralValue {
+    // Lists and maps can contain themselves, so `comparing` holds the pairs
+    // of them being compared further up. Meeting a pair again adds nothing
+    // that could make them differ.
+    fn equals(&self, other: &Self, comparing: &mut HashSet<(*const (), *const ())>) -> bool {
         match (self, other) {
             (Number(x), Number(y)) => x == y,
-            (
-                Callable(CallableImpl::LoxFunction(LoxFunctionImpl { name, arity, .. })),
-                Callable(CallableImpl::LoxFunction(LoxFunctionImpl {
-                    name: name2,
-                    arity: arity2,
-                    ..
-                })),
-            ) => name == name2 && arity == arity2,
-            (
-                Callable(CallableImpl::NativeFunction(NativeFunctionImpl { name, arity, .. })),
-                Callable(CallableImpl::NativeFunction(NativeFunctionImpl {
-                    name: name2,
-                    arity: arity2,
-                    ..
-                })),
-            ) => name == name2 && arity == arity2,
+            (Callable(x), Callable(y)) => x == y,
+            // Classes and instances are only equal to themselves
+            (LoxClass(x), LoxClass(y)) => Rc::ptr_eq(x, y),
+            (LoxInstance(x), LoxInstance(y)) => Rc::ptr_eq(x, y),
             (StringValue(x), StringValue(y)) => x == y,
+            (List(x), List(y)) => {
+                let pair = (gc::address(x), gc::address(y));
+                if Rc::ptr_eq(x, y) || !comparing.insert(pair) {
+                    return true;
+                }
+                let (x, y) = (x.borrow(), y.borrow());
+                let equal = x.len() == y.len()
+                    && x.iter().zip(y.iter()).all(|(x, y)| x.equals(y, comparing));
+                comparing.remove(&pair);
+                equal
+            }
+            (Map(x), Map(y)) => {
+                let pair = (gc::address(x), gc::address(y));
+                if Rc::ptr_eq(x, y) || !comparing.insert(pair) {
+                    return true;
+                }
+                let (x, y) = (x.borrow(), y.borrow());
+                let equal = x.len() == y.len()
+                    && x.iter()
+                        .all(|(key, x)| y.get(key).is_some_and(|y| x.equals(y, comparing)));
+                comparing.remove(&pair);
+                equal
+            }
             (True, True) => true,
             (False, False) => true,
             (Nil, Nil) => true,
@@ -89,7 +223,7 @@ impl PartialEq for LiteralValue {
 
 fn unwrap_as_f64(literal: Option<scanner::LiteralValue>) -> f64 {
     match literal {
-        Some(scanner::LiteralValue::FValue(x)) => x as f64,
+        Some(scanner::LiteralValue::FValue(x)) => x,
         _ => panic!("Could not unwrap as f64"),
     }
 }
@@ -101,47 +235,66 @@ fn unwrap_as_string(literal: Option<scanner::LiteralValue>) -> String {
     }
 }
 
-macro_rules! class_name {
-    ($class:expr) => {{
-        if let LiteralValue::LoxClass {
-            name,
-            methods: _,
-            superclass: _,
-        } = &**$class
-        {
-            name
-        } else {
-            panic!("Unreachable")
-        }
-    }};
-}
-
 impl LiteralValue {
     #[allow(clippy::inherent_to_string)]
     pub fn to_string(&self) -> String {
+        self.show(&mut HashSet::new())
+    }
+
+    // `showing` holds the lists and maps being printed further up, which a
+    // list or map that contains itself prints as `[...]` or `#{...}`
+    fn show(&self, showing: &mut HashSet<*const ()>) -> String {
         match self {
             LiteralValue::Number(x) => x.to_string(),
             LiteralValue::StringValue(x) => format!("\"{}\"", x),
             LiteralValue::True => "true".to_string(),
             LiteralValue::False => "false".to_string(),
             LiteralValue::Nil => "nil".to_string(),
-            LiteralValue::Callable(CallableImpl::LoxFunction(LoxFunctionImpl {
-                name,
-                arity,
-                ..
-            })) => format!("{name}/{arity}"),
-            LiteralValue::Callable(CallableImpl::NativeFunction(NativeFunctionImpl {
-                name,
-                arity,
-                ..
-            })) => format!("{name}/{arity}"),
-            LiteralValue::LoxClass {
-                name,
-                methods: _,
-                superclass: _,
-            } => format!("Class '{name}'"),
-            LiteralValue::LoxInstance { class, fields: _ } => {
-                format!("Instance of '{}'", class_name!(class))
+            LiteralValue::Callable(callable @ (LoxFunction(_) | Closure(_))) => {
+                format!("<fn {}/{}>", callable.name(), callable.arity())
+            }
+            LiteralValue::Callable(NativeFunction(fun)) => {
+                format!("<native fn {}/{}>", fun.name, fun.arity)
+            }
+            LiteralValue::Callable(callable @ BoundMethod(bound)) => format!(
+                "<bound method {}.{}/{}>",
+                bound.receiver.to_type(),
+                callable.name(),
+                callable.arity()
+            ),
+            LiteralValue::LoxClass(class) => format!("Class '{}'", class.name),
+            LiteralValue::LoxInstance(instance) => {
+                format!("Instance of '{}'", instance.class.name)
+            }
+            LiteralValue::List(elements) => {
+                if !showing.insert(gc::address(elements)) {
+                    return "[...]".to_string();
+                }
+                let members = elements
+                    .borrow()
+                    .iter()
+                    .map(|element| element.show(showing))
+                    .collect::<Vec<String>>();
+                showing.remove(&gc::address(elements));
+                format!("[{}]", members.join(", "))
+            }
+            LiteralValue::Map(map) => {
+                if !showing.insert(gc::address(map)) {
+                    return "#{...}".to_string();
+                }
+                let entries = map.borrow();
+                let members = sorted_keys(&entries)
+                    .into_iter()
+                    .map(|key| {
+                        format!(
+                            "{}: {}",
+                            map_key_to_string(&key),
+                            entries[&key].show(showing)
+                        )
+                    })
+                    .collect::<Vec<String>>();
+                showing.remove(&gc::address(map));
+                format!("#{{{}}}", members.join(", "))
             }
         }
     }
@@ -154,12 +307,10 @@ impl LiteralValue {
             LiteralValue::False => "Boolean",
             LiteralValue::Nil => "nil",
             LiteralValue::Callable(_) => "Callable",
-            LiteralValue::LoxClass {
-                name: _,
-                methods: _,
-                superclass: _,
-            } => "Class",
-            LiteralValue::LoxInstance { class, fields: _ } => &class_name!(class),
+            LiteralValue::LoxClass(_) => "Class",
+            LiteralValue::LoxInstance(instance) => &instance.class.name,
+            LiteralValue::List(_) => "List",
+            LiteralValue::Map(_) => "Map",
         }
     }
 
@@ -182,53 +333,198 @@ impl LiteralValue {
         }
     }
 
+    /// Falsy values are `false`, `nil`, `0`, and empty strings, lists and maps.
+    /// Everything else, including functions, classes and instances, is truthy.
     pub fn is_falsy(&self) -> LiteralValue {
         match self {
             Number(x) => {
-                if *x == 0.0 as f64 {
+                if *x == 0.0 {
                     True
                 } else {
                     False
                 }
             }
             StringValue(s) => {
-                if s.len() == 0 {
+                if s.is_empty() {
                     True
                 } else {
                     False
                 }
             }
+            List(elements) => LiteralValue::from_bool(elements.borrow().is_empty()),
+            Map(entries) => LiteralValue::from_bool(entries.borrow().is_empty()),
             True => False,
             False => True,
             Nil => True,
-            Callable(_) => panic!("Cannot use Callable as a falsy value"),
-            LoxClass { .. } => panic!("Cannot use class as a falsy value"),
-            _ => panic!("Not valid as a boolean value"),
+            Callable(_) | LoxClass(_) | LoxInstance(_) => False,
         }
     }
 
     pub fn is_truthy(&self) -> LiteralValue {
         match self {
             Number(x) => {
-                if *x == 0.0 as f64 {
+                if *x == 0.0 {
                     False
                 } else {
                     True
                 }
             }
             StringValue(s) => {
-                if s.len() == 0 {
+                if s.is_empty() {
                     False
                 } else {
                     True
                 }
             }
+            List(elements) => LiteralValue::from_bool(!elements.borrow().is_empty()),
+            Map(entries) => LiteralValue::from_bool(!entries.borrow().is_empty()),
             True => True,
             False => False,
             Nil => False,
-            Callable(_) => panic!("Cannot use Callable as a truthy value"),
-            LoxClass { .. } => panic!("Cannot use class as a truthy value"),
-            _ => panic!("Not valid as a boolean value"),
+            Callable(_) | LoxClass(_) | LoxInstance(_) => True,
+        }
+    }
+
+    /// A new list, tracked by the cycle collector
+    pub fn list(elements: Vec<LiteralValue>) -> Self {
+        let elements = Rc::new(RefCell::new(elements));
+        gc::track(&elements);
+        List(elements)
+    }
+
+    /// A new map, tracked by the cycle collector
+    pub fn map(entries: HashMap<String, LiteralValue>) -> Self {
+        let entries = Rc::new(RefCell::new(entries));
+        gc::track(&entries);
+        Map(entries)
+    }
+
+    /// A new class, tracked by the cycle collector
+    pub fn class(class: LoxClassImpl) -> Self {
+        let class = Rc::new(class);
+        gc::track(&class);
+        LoxClass(class)
+    }
+
+    /// A new instance of `class` without fields, tracked by the cycle collector
+    pub fn instance(class: Rc<LoxClassImpl>) -> Self {
+        let instance = Rc::new(LoxInstanceImpl {
+            class,
+            fields: RefCell::new(HashMap::new()),
+        });
+        gc::track(&instance);
+        LoxInstance(instance)
+    }
+
+    /// Visits the heap objects the value refers to, for the cycle collector
+    pub fn trace(&self, visit: &mut dyn FnMut(*const ())) {
+        match self {
+            Callable(callable) => callable.trace(visit),
+            LoxClass(class) => visit(gc::address(class)),
+            LoxInstance(instance) => visit(gc::address(instance)),
+            List(elements) => visit(gc::address(elements)),
+            Map(entries) => visit(gc::address(entries)),
+            Number(_) | StringValue(_) | True | False | Nil => (),
+        }
+    }
+}
+
+// The containers of values are what cycles go through
+
+impl Trace for RefCell<Vec<LiteralValue>> {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        match self.try_borrow() {
+            Ok(elements) => {
+                elements.iter().for_each(|element| element.trace(visit));
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut elements) = self.try_borrow_mut() {
+            elements.clear();
+        }
+    }
+}
+
+impl Trace for RefCell<HashMap<String, LiteralValue>> {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        match self.try_borrow() {
+            Ok(entries) => {
+                entries.values().for_each(|value| value.trace(visit));
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut entries) = self.try_borrow_mut() {
+            entries.clear();
+        }
+    }
+}
+
+impl Trace for LoxInstanceImpl {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        match self.fields.try_borrow() {
+            Ok(fields) => {
+                visit(gc::address(&self.class));
+                fields.values().for_each(|value| value.trace(visit));
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut fields) = self.fields.try_borrow_mut() {
+            fields.clear();
+        }
+    }
+}
+
+impl Trace for LoxFunctionImpl {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        self.env.trace(visit);
+        true
+    }
+
+    // What a function refers to can't change; the variables it captured
+    // are cleared themselves
+    fn clear(&self) {}
+}
+
+impl Trace for LoxClassImpl {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        self.methods.values().for_each(|method| method.trace(visit));
+        self.superclass
+            .iter()
+            .for_each(|superclass| visit(gc::address(superclass)));
+        true
+    }
+
+    // A class can't change once it is declared; the cycles it is part of go
+    // through its methods and are broken where they can be changed
+    fn clear(&self) {}
+}
+
+impl Trace for RefCell<LiteralValue> {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        match self.try_borrow() {
+            Ok(value) => {
+                value.trace(visit);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut value) = self.try_borrow_mut() {
+            *value = Nil;
         }
     }
 }
@@ -236,6 +532,7 @@ impl LiteralValue {
 use crate::stmt::Stmt;
 
 #[derive(Clone)]
+#[allow(clippy::vec_box)]
 pub enum Expr {
     AnonFunction {
         id: usize,
@@ -243,6 +540,10 @@ pub enum Expr {
         arguments: Vec<Token>,
         body: Vec<Box<Stmt>>,
     },
+    Array {
+        id: usize,
+        elements: Vec<Expr>,
+    },
     Assign {
         id: usize,
         name: Token,
@@ -271,6 +572,21 @@ pub enum Expr {
         id: usize,
         expression: Box<Expr>,
     },
+    // xs[i]
+    Index {
+        id: usize,
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    },
+    // xs[i] = value
+    IndexSet {
+        id: usize,
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
     Literal {
         id: usize,
         value: LiteralValue,
@@ -281,6 +597,11 @@ pub enum Expr {
         operator: Token,
         right: Box<Expr>,
     },
+    // #{ key: value, ... }
+    Object {
+        id: usize,
+        entries: Vec<(String, Expr)>,
+    },
     Set {
         id: usize,
         object: Box<Expr>,
@@ -301,6 +622,15 @@ pub enum Expr {
         operator: Token,
         right: Box<Expr>,
     },
+    // target op= value, ++target, target++ (and the -- forms), where target is a
+    // variable, a property or an index. Increments use a literal 1 as the value.
+    Update {
+        id: usize,
+        target: Box<Expr>,
+        operator: Token,
+        value: Box<Expr>,
+        postfix: bool,
+    },
     Variable {
         id: usize,
         name: Token,
@@ -338,6 +668,7 @@ impl Expr {
                 arguments: _,
                 body: _,
             } => *id,
+            Expr::Array { id, elements: _ } => *id,
             Expr::Assign {
                 id,
                 name: _,
@@ -362,6 +693,19 @@ impl Expr {
                 name: _,
             } => *id,
             Expr::Grouping { id, expression: _ } => *id,
+            Expr::Index {
+                id,
+                object: _,
+                bracket: _,
+                index: _,
+            } => *id,
+            Expr::IndexSet {
+                id,
+                object: _,
+                bracket: _,
+                index: _,
+                value: _,
+            } => *id,
             Expr::Literal { id, value: _ } => *id,
             Expr::Logical {
                 id,
@@ -369,6 +713,7 @@ impl Expr {
                 operator: _,
                 right: _,
             } => *id,
+            Expr::Object { id, entries: _ } => *id,
             Expr::Set {
                 id,
                 object: _,
@@ -386,6 +731,13 @@ impl Expr {
                 operator: _,
                 right: _,
             } => *id,
+            Expr::Update {
+                id,
+                target: _,
+                operator: _,
+                value: _,
+                postfix: _,
+            } => *id,
             Expr::Variable { id, name: _ } => *id,
         }
     }
@@ -402,6 +754,13 @@ impl Expr {
                 arguments,
                 body: _,
             } => format!("anon/{}", arguments.len()),
+            Expr::Array { id: _, elements } => format!(
+                "(array{})",
+                elements
+                    .iter()
+                    .map(|element| format!(" {}", element.to_string()))
+                    .collect::<String>()
+            ),
             Expr::Assign { id: _, name, value } => format!("({name:?} = {}", value.to_string()),
             Expr::Binary {
                 id: _,
@@ -428,7 +787,25 @@ impl Expr {
             Expr::Grouping { id: _, expression } => {
                 format!("(group {})", (*expression).to_string())
             }
-            Expr::Literal { id: _, value } => format!("{}", value.to_string()),
+            Expr::Index {
+                id: _,
+                object,
+                bracket: _,
+                index,
+            } => format!("(index {} {})", object.to_string(), index.to_string()),
+            Expr::IndexSet {
+                id: _,
+                object,
+                bracket: _,
+                index,
+                value,
+            } => format!(
+                "(index-set {} {} {})",
+                object.to_string(),
+                index.to_string(),
+                value.to_string()
+            ),
+            Expr::Literal { id: _, value } => value.to_string(),
             Expr::Logical {
                 id: _,
                 left,
@@ -440,6 +817,13 @@ impl Expr {
                 left.to_string(),
                 right.to_string()
             ),
+            Expr::Object { id: _, entries } => format!(
+                "(object{})",
+                entries
+                    .iter()
+                    .map(|(key, value)| format!(" ({} {})", key, value.to_string()))
+                    .collect::<String>()
+            ),
             Expr::Set {
                 id: _,
                 object,
@@ -451,7 +835,7 @@ impl Expr {
                 name.to_string(),
                 value.to_string()
             ),
-            Expr::This { id: _, keyword: _ } => format!("(this)"),
+            Expr::This { id: _, keyword: _ } => "(this)".to_string(),
             Expr::Super {
                 id: _,
                 keyword: _,
@@ -466,15 +850,35 @@ impl Expr {
                 let right_str = (*right).to_string();
                 format!("({} {})", operator_str, right_str)
             }
+            Expr::Update {
+                id: _,
+                target,
+                operator,
+                value,
+                postfix,
+            } => match operator.token_type {
+                TokenType::PlusPlus | TokenType::MinusMinus if *postfix => {
+                    format!("(post{} {})", operator.lexeme, target.to_string())
+                }
+                TokenType::PlusPlus | TokenType::MinusMinus => {
+                    format!("({} {})", operator.lexeme, target.to_string())
+                }
+                _ => format!(
+                    "({} {} {})",
+                    operator.lexeme,
+                    target.to_string(),
+                    value.to_string()
+                ),
+            },
             Expr::Variable { id: _, name } => format!("(var {})", name.lexeme),
         }
     }
 
-    pub fn evaluate(&self, environment: Environment) -> Result<LiteralValue, String> {
+    pub fn evaluate(&self, environment: &Environment) -> Result<LiteralValue, MossError> {
         match self {
             Expr::AnonFunction {
                 id: _,
-                paren: _,
+                paren,
                 arguments,
                 body,
             } => {
@@ -483,94 +887,81 @@ impl Expr {
                 let arguments: Vec<Token> = arguments.iter().map(|t| (*t).clone()).collect();
                 let body: Vec<Box<Stmt>> = body.iter().map(|b| (*b).clone()).collect();
 
-                let callable_impl = CallableImpl::LoxFunction(LoxFunctionImpl {
+                let callable_impl = CallableImpl::lox_function(LoxFunctionImpl {
                     name: "anon_funciton".to_string(),
                     arity,
-                    parent_env: environment.clone(),
+                    env: environment.capture(paren.offset),
                     params: arguments,
                     body,
+                    initializer: false,
                 });
 
                 Ok(Callable(callable_impl))
             }
+            Expr::Array { id: _, elements } => {
+                let mut values = vec![];
+                for element in elements {
+                    values.push(element.evaluate(environment)?);
+                }
+                Ok(LiteralValue::list(values))
+            }
             Expr::Assign { id: _, name, value } => {
-                let new_value = (*value).evaluate(environment.clone())?;
+                let new_value = (*value).evaluate(environment)?;
                 let assign_success =
                     environment.assign(&name.lexeme, new_value.clone(), self.get_id());
 
                 if assign_success {
                     Ok(new_value)
                 } else {
-                    Err(format!("Variable {} has not been declared", name.lexeme))
+                    Err(MossError::runtime(
+                        format!("Variable {} has not been declared", name.lexeme),
+                        name,
+                    ))
                 }
             }
             Expr::Variable { id: _, name } => match environment.get(&name.lexeme, self.get_id()) {
                 Some(value) => Ok(value.clone()),
-                None => Err(format!(
-                    "Variable '{}' has not been declared at distance {:?}",
-                    name.lexeme,
-                    environment.get_distance(self.get_id())
+                None => Err(MossError::runtime(
+                    format!("Variable '{}' has not been declared", name.lexeme),
+                    name,
                 )),
             },
             Expr::Call {
                 id: _,
                 callee,
-                paren: _,
+                paren,
                 arguments,
             } => {
                 // Look up function definition in environment
                 // let callable_distance = locals.borrow().get(&self.get_id());
-                let callable: LiteralValue = (*callee).evaluate(environment.clone())?;
-                let callable_clone = callable.clone();
-                match callable {
-                    Callable(CallableImpl::LoxFunction(loxfun)) => {
-                        run_lox_function(loxfun, arguments, environment)
-                    }
-                    Callable(CallableImpl::NativeFunction(nativefun)) => {
-                        let mut evaluated_arguments = vec![];
-                        for argument in arguments {
-                            evaluated_arguments.push(argument.evaluate(environment.clone())?);
-                        }
-                        Ok((nativefun.fun)(&evaluated_arguments))
-                    }
-                    LoxClass {
-                        name: _,
-                        methods,
-                        superclass: _,
-                    } => {
-                        let instance = LoxInstance {
-                            class: Box::new(callable_clone.clone()),
-                            fields: Rc::new(RefCell::new(vec![])),
-                        };
-
-                        // Call constructor if present
-                        if let Some(init_method) = methods.get("init") {
-                            if init_method.arity != arguments.len() {
-                                return Err(
-                                    "Invalid number of arguments in constructor".to_string()
-                                );
-                            }
-
-                            // let new_env = environment.enclose();
-                            // new_env.define("this".to_string(), instance.clone());
-                            // let mut init_method = init_method.clone();
-                            // init_method.parent_env = new_env.clone();
-                            let mut init_method = init_method.clone();
-                            init_method.parent_env = init_method.parent_env.enclose();
-                            init_method
-                                .parent_env
-                                .define("this".to_string(), instance.clone());
-
-                            if let Err(msg) = run_lox_function(init_method, arguments, environment)
-                            {
-                                return Err(msg);
-                            }
-                        }
-
-                        Ok(instance)
+                let callable: LiteralValue = (*callee).evaluate(environment)?;
+                let name = match &callable {
+                    Callable(fun) => fun.name().to_string(),
+                    LoxClass(class) => class.name.clone(),
+                    other => {
+                        return Err(MossError::runtime(
+                            format!("{} is not callable", other.to_type()),
+                            paren,
+                        ))
                     }
-                    other => Err(format!("{} is not callable", other.to_type())),
+                };
+
+                let mut evaluated_arguments = vec![];
+                for argument in arguments {
+                    evaluated_arguments.push(argument.evaluate(environment)?);
                 }
+
+                let result = environment
+                    .push_call(&name, paren.span())
+                    .map_err(|message| MossError::runtime(message, paren))
+                    .and_then(|()| {
+                        stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT, || {
+                            call_value(callable, evaluated_arguments, Some(paren))
+                        })
+                    });
+                let result = result.map_err(|error| error.with_trace(environment.call_stack()));
+                environment.pop_call();
+                result
             }
             Expr::Literal { id: _, value } => Ok((*value).clone()),
             Expr::Logical {
@@ -580,66 +971,42 @@ impl Expr {
                 right,
             } => match operator.token_type {
                 TokenType::Or => {
-                    let lhs_value = left.evaluate(environment.clone())?;
+                    let lhs_value = left.evaluate(environment)?;
                     let lhs_true = lhs_value.is_truthy();
                     if lhs_true == True {
                         Ok(lhs_value)
                     } else {
-                        right.evaluate(environment.clone())
+                        right.evaluate(environment)
                     }
                 }
                 TokenType::And => {
-                    let lhs_value = left.evaluate(environment.clone())?;
+                    let lhs_value = left.evaluate(environment)?;
                     let lhs_true = lhs_value.is_truthy();
                     if lhs_true == False {
                         Ok(lhs_true)
                     } else {
-                        right.evaluate(environment.clone())
+                        right.evaluate(environment)
                     }
                 }
-                ttype => Err(format!("Invalid token in logical expression: {}", ttype)),
+                ttype => Err(MossError::runtime(
+                    format!("Invalid token in logical expression: {}", ttype),
+                    operator,
+                )),
             },
             Expr::Get {
                 id: _,
                 object,
                 name,
             } => {
-                let obj_value = object.evaluate(environment.clone())?;
-                // Now obj_value should be a LoxInstance
-                if let LoxInstance { class, fields } = obj_value.clone() {
-                    for (field_name, value) in (*fields.borrow()).iter() {
-                        // Are we getting a field on the object?
-                        if field_name == &name.lexeme {
-                            return Ok(value.clone());
-                        }
-                    }
-                    // Are we getting a method on the object?
-                    // TODO Make a function that finds a method on a class by looking first at the
-                    // class, then at the superclasses in a recursive manner
-
-                    if let LoxClass {
-                        name: _,
-                        methods: _,
-                        superclass: _,
-                    } = class.as_ref()
-                    {
-                        if let Some(method) = find_method(&name.lexeme, *class.clone()) {
-                            let mut callable_impl = method.clone();
-                            let new_env = callable_impl.parent_env.enclose();
-                            new_env.define("this".to_string(), obj_value.clone());
-                            callable_impl.parent_env = new_env;
-                            return Ok(Callable(LoxFunction(callable_impl)));
-                        }
-                    } else {
-                        panic!("The class field on an instance was not a LoxClass");
-                    }
-                    Err(format!("No field named {} on this instance", name.lexeme))
-                } else {
-                    Err(format!(
-                        "Cannot access property on type {}",
-                        obj_value.to_type()
-                    ))
+                let obj_value = object.evaluate(environment)?;
+                get_property(&obj_value, &name.lexeme).at(name)
+            }
+            Expr::Object { id: _, entries } => {
+                let mut values = HashMap::new();
+                for (key, value) in entries {
+                    values.insert(key.clone(), value.evaluate(environment)?);
                 }
+                Ok(LiteralValue::map(values))
             }
             Expr::Set {
                 id: _,
@@ -647,96 +1014,137 @@ impl Expr {
                 name,
                 value,
             } => {
-                let obj_value = object.evaluate(environment.clone())?;
-                if let LoxInstance { class: _, fields } = obj_value {
-                    let value = value.evaluate(environment.clone())?;
-
-                    let mut idx = 0;
-                    let mut found = false;
-                    for i in 0..(*fields.borrow()).len() {
-                        let field_name = &(*fields.borrow())[i].0;
-                        if field_name == &name.lexeme {
-                            idx = i;
-                            found = true;
-                            break;
-                        }
-                    }
-
-                    if found {
-                        (*fields.borrow_mut())[idx].1 = value.clone();
-                    } else {
-                        (*fields.borrow_mut()).push((name.lexeme.clone(), value));
-                    }
-
+                let obj_value = object.evaluate(environment)?;
+                if let LoxInstance(_) | Map(_) = obj_value {
+                    let value = value.evaluate(environment)?;
+                    set_property(&obj_value, &name.lexeme, value).at(name)?;
                     Ok(Nil)
                 } else {
-                    Err(format!(
-                        "Cannot set property on type {}",
-                        obj_value.to_type()
+                    Err(MossError::runtime(
+                        format!("Cannot set property on type {}", obj_value.to_type()),
+                        name,
                     ))
                 }
             }
-            Expr::This { id: _, keyword: _ } => {
-                let this = environment
-                    .get("this", self.get_id())
-                    .expect("Couldn't lookup 'this'");
-                Ok(this)
-            }
+            Expr::This { id: _, keyword } => environment
+                .get("this", self.get_id())
+                .ok_or_else(|| MossError::runtime("Couldn't lookup 'this'", keyword)),
             Expr::Super {
                 id: _,
-                keyword: _,
+                keyword,
                 method,
             } => {
-                let superclass = environment.get("super", self.get_id()).expect(&format!(
-                    "Couldn't lookup 'super':\n---------------\n{}---------------\n",
-                    environment.dump(0)
-                ));
+                let superclass = environment
+                    .get("super", self.get_id())
+                    .ok_or_else(|| MossError::runtime("Couldn't lookup 'super'", keyword))?;
 
-                let instance = environment.get_this_instance(self.get_id()).unwrap();
+                let instance = environment
+                    .get_this_instance(self.get_id())
+                    .ok_or_else(|| {
+                        MossError::runtime("Couldn't lookup 'this' for 'super'", keyword)
+                    })?;
 
                 // let new_env = environment.enclose();
                 // new_env.define("this".to_string(), instance.clone());
 
-                if let LoxClass {
-                    name: _,
-                    methods,
-                    superclass: _,
-                } = superclass.clone()
-                {
-                    if let Some(method_value) = methods.get(&method.lexeme) {
-                        let mut method = method_value.clone();
-                        method.parent_env = method.parent_env.enclose();
-                        method
-                            .parent_env
-                            .define("this".to_string(), instance.clone());
-                        Ok(Callable(LoxFunction(method)))
+                if let LoxClass(class) = &superclass {
+                    if let Some(method_value) = class.find_method(&method.lexeme) {
+                        Ok(Callable(method_value.bind(instance.clone())))
                     } else {
-                        Err(format!(
-                            "No method named {} on superclass {}",
-                            method.lexeme,
-                            superclass.to_type()
+                        Err(MossError::runtime(
+                            format!(
+                                "No method named {} on superclass {}",
+                                method.lexeme, class.name
+                            ),
+                            method,
                         ))
                     }
                 } else {
-                    panic!("The superclass field on an instance was not a LoxClass");
+                    Err(MossError::runtime(
+                        format!("Superclass must be a class, not {}", superclass.to_type()),
+                        keyword,
+                    ))
                 }
             }
             Expr::Grouping { id: _, expression } => expression.evaluate(environment),
+            Expr::Index {
+                id: _,
+                object,
+                bracket,
+                index,
+            } => {
+                let object = object.evaluate(environment)?;
+                let index = index.evaluate(environment)?;
+                get_index(&object, &index).at(bracket)
+            }
+            Expr::IndexSet {
+                id: _,
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                let object = object.evaluate(environment)?;
+                let index = index.evaluate(environment)?;
+                let value = value.evaluate(environment)?;
+                set_index(&object, &index, value.clone()).at(bracket)?;
+                Ok(value)
+            }
+            Expr::Update {
+                id: _,
+                target,
+                operator,
+                value,
+                postfix,
+            } => {
+                let op = update_operator(operator.token_type);
+                // The object and index of the target are evaluated only once
+                let (old, new) = match target.as_ref() {
+                    Expr::Variable { id: _, name } => {
+                        let old = target.evaluate(environment)?;
+                        let value = value.evaluate(environment)?;
+                        let new = binary_op(&old, op, &value).at(operator)?;
+                        environment.assign(&name.lexeme, new.clone(), target.get_id());
+                        (old, new)
+                    }
+                    Expr::Get {
+                        id: _,
+                        object,
+                        name,
+                    } => {
+                        let object = object.evaluate(environment)?;
+                        let old = get_property(&object, &name.lexeme).at(name)?;
+                        let value = value.evaluate(environment)?;
+                        let new = binary_op(&old, op, &value).at(operator)?;
+                        set_property(&object, &name.lexeme, new.clone()).at(name)?;
+                        (old, new)
+                    }
+                    Expr::Index {
+                        id: _,
+                        object,
+                        bracket,
+                        index,
+                    } => {
+                        let object = object.evaluate(environment)?;
+                        let index = index.evaluate(environment)?;
+                        let old = get_index(&object, &index).at(bracket)?;
+                        let value = value.evaluate(environment)?;
+                        let new = binary_op(&old, op, &value).at(operator)?;
+                        set_index(&object, &index, new.clone()).at(bracket)?;
+                        (old, new)
+                    }
+                    _ => panic!("Parser produced an update of an invalid target"),
+                };
+
+                Ok(if *postfix { old } else { new })
+            }
             Expr::Unary {
                 id: _,
                 operator,
                 right,
             } => {
                 let right = right.evaluate(environment)?;
-
-                match (&right, operator.token_type) {
-                    (Number(x), TokenType::Minus) => Ok(Number(-x)),
-                    (_, TokenType::Minus) => {
-                        Err(format!("Minus not implemented for {}", right.to_type()))
-                    }
-                    (any, TokenType::Bang) => Ok(any.is_falsy()),
-                    (_, ttype) => Err(format!("{} is not a valid unary operator", ttype)),
-                }
+                unary_op(operator.token_type, &right).at(operator)
             }
             Expr::Binary {
                 id: _,
@@ -744,55 +1152,10 @@ impl Expr {
                 operator,
                 right,
             } => {
-                let left = left.evaluate(environment.clone())?;
-                let right = right.evaluate(environment.clone())?;
-
-                match (&left, operator.token_type, &right) {
-                    (Number(x), TokenType::Plus, Number(y)) => Ok(Number(x + y)),
-                    (Number(x), TokenType::Minus, Number(y)) => Ok(Number(x - y)),
-                    (Number(x), TokenType::Star, Number(y)) => Ok(Number(x * y)),
-                    (Number(x), TokenType::Slash, Number(y)) => Ok(Number(x / y)),
-                    (Number(x), TokenType::Greater, Number(y)) => {
-                        Ok(LiteralValue::from_bool(x > y))
-                    }
-                    (Number(x), TokenType::GreaterEqual, Number(y)) => {
-                        Ok(LiteralValue::from_bool(x >= y))
-                    }
-                    (Number(x), TokenType::Less, Number(y)) => Ok(LiteralValue::from_bool(x < y)),
-                    (Number(x), TokenType::LessEqual, Number(y)) => {
-                        Ok(LiteralValue::from_bool(x <= y))
-                    }
-
-                    (StringValue(_), op, Number(_)) => {
-                        Err(format!("{} is not defined for string and number", op))
-                    }
-                    (Number(_), op, StringValue(_)) => {
-                        Err(format!("{} is not defined for string and number", op))
-                    }
-
-                    (StringValue(s1), TokenType::Plus, StringValue(s2)) => {
-                        Ok(StringValue(format!("{}{}", s1, s2)))
-                    }
+                let left = left.evaluate(environment)?;
+                let right = right.evaluate(environment)?;
 
-                    (x, TokenType::BangEqual, y) => Ok(LiteralValue::from_bool(x != y)),
-                    (x, TokenType::EqualEqual, y) => Ok(LiteralValue::from_bool(x == y)),
-                    (StringValue(s1), TokenType::Greater, StringValue(s2)) => {
-                        Ok(LiteralValue::from_bool(s1 > s2))
-                    }
-                    (StringValue(s1), TokenType::GreaterEqual, StringValue(s2)) => {
-                        Ok(LiteralValue::from_bool(s1 >= s2))
-                    }
-                    (StringValue(s1), TokenType::Less, StringValue(s2)) => {
-                        Ok(LiteralValue::from_bool(s1 < s2))
-                    }
-                    (StringValue(s1), TokenType::LessEqual, StringValue(s2)) => {
-                        Ok(LiteralValue::from_bool(s1 <= s2))
-                    }
-                    (x, ttype, y) => Err(format!(
-                        "{} is not implemented for operands {:?} and {:?}",
-                        ttype, x, y
-                    )),
-                }
+                binary_op(&left, operator.token_type, &right).at(operator)
             }
         }
     }
@@ -803,64 +1166,266 @@ impl Expr {
     }
 }
 
+/// Calls a function or constructs an instance of a class, with the arguments
+/// already evaluated. Errors of the call itself point at `paren`, the closing
+/// parenthesis of the call, unless it was made by the host.
+pub fn call_value(
+    callable: LiteralValue,
+    arguments: Vec<LiteralValue>,
+    paren: Option<&Token>,
+) -> Result<LiteralValue, MossError> {
+    let error =
+        |message: String| MossError::new(ErrorKind::Runtime, message, paren.map(Token::span));
+
+    match callable {
+        Callable(CallableImpl::LoxFunction(loxfun)) => {
+            run_lox_function(&loxfun, None, arguments, paren)
+        }
+        Callable(CallableImpl::BoundMethod(bound)) => match *bound.method {
+            LoxFunction(method) => {
+                run_lox_function(&method, Some(*bound.receiver), arguments, paren)
+            }
+            other => Err(error(format!("{} is not callable", other.name()))),
+        },
+        Callable(CallableImpl::NativeFunction(nativefun)) => {
+            check_arity(&nativefun.name, nativefun.arity, arguments.len()).map_err(error)?;
+            (nativefun.fun)(&arguments).map_err(error)
+        }
+        LoxClass(class) => {
+            let instance = LiteralValue::instance(class.clone());
+
+            // Call the constructor, which may be inherited. A class without
+            // one takes no arguments.
+            let init = class.find_method("init");
+            if init.as_ref().map_or(0, CallableImpl::arity) != arguments.len() {
+                return Err(error(
+                    "Invalid number of arguments in constructor".to_string(),
+                ));
+            }
+            if let Some(LoxFunction(init)) = init {
+                run_lox_function(&init, Some(instance.clone()), arguments, paren)?;
+            }
+
+            Ok(instance)
+        }
+        other => Err(error(format!("{} is not callable", other.to_type()))),
+    }
+}
+
 pub fn run_lox_function(
-    loxfun: LoxFunctionImpl,
-    arguments: &Vec<Expr>,
-    eval_env: Environment,
-) -> Result<LiteralValue, String> {
-    // Do some checking (correct number of args?)
-    if arguments.len() != loxfun.arity {
+    loxfun: &LoxFunctionImpl,
+    this: Option<LiteralValue>,
+    arg_vals: Vec<LiteralValue>,
+    paren: Option<&Token>,
+) -> Result<LiteralValue, MossError> {
+    check_arity(&loxfun.name, loxfun.arity, arg_vals.len())
+        .map_err(|message| MossError::new(ErrorKind::Runtime, message, paren.map(Token::span)))?;
+
+    let fun_env = loxfun.env.enclose();
+    if let Some(this) = &this {
+        fun_env.define("this".to_string(), this.clone());
+    }
+    for (i, val) in arg_vals.iter().enumerate() {
+        fun_env.define(loxfun.params[i].lexeme.clone(), (*val).clone());
+    }
+
+    // The function may come from another module than its caller
+    let file = fun_env.file().to_string();
+    let mut int = Interpreter::with_env(fun_env);
+    let flow = int
+        .interpret(loxfun.body.iter().map(|stmt| stmt.as_ref()).collect())
+        .map_err(|error| error.in_file(&file))?;
+    if let (true, Some(this)) = (loxfun.initializer, this) {
+        return Ok(this);
+    }
+    match flow {
+        ControlFlow::Return(value) => Ok(value),
+        ControlFlow::Normal => Ok(LiteralValue::Nil),
+        ControlFlow::Break | ControlFlow::Continue => {
+            panic!("Resolver allowed break or continue outside of a loop")
+        }
+    }
+}
+
+pub fn check_arity(name: &str, arity: usize, arg_count: usize) -> Result<(), String> {
+    if arg_count != arity {
         return Err(format!(
             "Callable {} expected {} arguments but got {}",
-            loxfun.name,
-            loxfun.arity,
-            arguments.len()
+            name, arity, arg_count
         ));
     }
+    Ok(())
+}
+
+pub fn get_property(object: &LiteralValue, name: &str) -> Result<LiteralValue, String> {
+    match object {
+        LoxInstance(instance) => {
+            if let Some(value) = instance.fields.borrow().get(name) {
+                return Ok(value.clone());
+            }
+            if let Some(method) = instance.class.find_method(name) {
+                return Ok(Callable(method.bind(object.clone())));
+            }
+            Err(format!("No field named {} on this instance", name))
+        }
+        Map(entries) => entries
+            .borrow()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("No key named {} in this map", name)),
+        other => Err(format!(
+            "Cannot access property on type {}",
+            other.to_type()
+        )),
+    }
+}
+
+pub fn set_property(object: &LiteralValue, name: &str, value: LiteralValue) -> Result<(), String> {
+    match object {
+        LoxInstance(instance) => {
+            instance.fields.borrow_mut().insert(name.to_string(), value);
+            Ok(())
+        }
+        Map(entries) => {
+            entries.borrow_mut().insert(name.to_string(), value);
+            Ok(())
+        }
+        other => Err(format!("Cannot set property on type {}", other.to_type())),
+    }
+}
+
+/// Map keys in sorted order, so that printing and iterating a map is deterministic
+pub fn sorted_keys(entries: &HashMap<String, LiteralValue>) -> Vec<String> {
+    let mut keys: Vec<String> = entries.keys().cloned().collect();
+    keys.sort();
+    keys
+}
 
-    // Evaluate arguments
-    let mut arg_vals = vec![];
-    for arg in arguments {
-        let val = arg.evaluate(eval_env.clone())?;
-        arg_vals.push(val);
+// Keys print bare when they could be written as identifiers in a literal
+fn map_key_to_string(key: &str) -> String {
+    let is_identifier = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_identifier {
+        key.to_string()
+    } else {
+        format!("\"{}\"", key)
     }
+}
 
-    let fun_env = loxfun.parent_env.enclose();
+fn map_key(index: &LiteralValue) -> Result<&str, String> {
+    match index {
+        StringValue(key) => Ok(key),
+        other => Err(format!("Map key must be a String, not {}", other.to_type())),
+    }
+}
 
-    for (i, val) in arg_vals.iter().enumerate() {
-        fun_env.define(loxfun.params[i].lexeme.clone(), (*val).clone());
+fn list_index(index: &LiteralValue, len: usize) -> Result<usize, String> {
+    match index {
+        Number(x) if x.fract() != 0.0 => Err("Index must be a whole number".to_string()),
+        Number(x) if *x >= 0.0 && (*x as usize) < len => Ok(*x as usize),
+        Number(x) => Err(format!("Index {} out of bounds for length {}", x, len)),
+        other => Err(format!("Index must be a Number, not {}", other.to_type())),
     }
+}
 
-    let mut int = Interpreter::with_env(fun_env);
-    for i in 0..(loxfun.body.len()) {
-        let result = int.interpret(vec![&loxfun.body[i]]);
-        if let Err(e) = result {
-            return Err(e);
+pub fn get_index(object: &LiteralValue, index: &LiteralValue) -> Result<LiteralValue, String> {
+    match object {
+        List(elements) => {
+            let elements = elements.borrow();
+            let idx = list_index(index, elements.len())?;
+            Ok(elements[idx].clone())
         }
-        if let Some(value) = int.specials.get("return") {
-            return Ok(value.clone());
+        StringValue(s) => {
+            let idx = list_index(index, s.chars().count())?;
+            Ok(StringValue(s.chars().nth(idx).unwrap().to_string()))
         }
+        Map(_) => get_property(object, map_key(index)?),
+        other => Err(format!("Cannot index into type {}", other.to_type())),
+    }
+}
+
+pub fn set_index(
+    object: &LiteralValue,
+    index: &LiteralValue,
+    value: LiteralValue,
+) -> Result<(), String> {
+    match object {
+        List(elements) => {
+            let mut elements = elements.borrow_mut();
+            let idx = list_index(index, elements.len())?;
+            elements[idx] = value;
+            Ok(())
+        }
+        Map(_) => set_property(object, map_key(index)?, value),
+        other => Err(format!(
+            "Cannot assign to an index of type {}",
+            other.to_type()
+        )),
+    }
+}
+
+pub fn unary_op(operator: TokenType, right: &LiteralValue) -> Result<LiteralValue, String> {
+    match (right, operator) {
+        (Number(x), TokenType::Minus) => Ok(Number(-x)),
+        (_, TokenType::Minus) => Err(format!("Minus not implemented for {}", right.to_type())),
+        (any, TokenType::Bang) => Ok(any.is_falsy()),
+        (_, ttype) => Err(format!("{} is not a valid unary operator", ttype)),
     }
+}
 
-    Ok(LiteralValue::Nil)
+/// The binary operator applied by a compound assignment, increment or decrement
+pub fn update_operator(operator: TokenType) -> TokenType {
+    match operator {
+        TokenType::PlusEqual | TokenType::PlusPlus => TokenType::Plus,
+        TokenType::MinusEqual | TokenType::MinusMinus => TokenType::Minus,
+        TokenType::StarEqual => TokenType::Star,
+        TokenType::SlashEqual => TokenType::Slash,
+        other => panic!("{} is not an update operator", other),
+    }
 }
 
-pub fn find_method(name: &str, class: LiteralValue) -> Option<LoxFunctionImpl> {
-    if let LoxClass {
-        name: _,
-        methods,
-        superclass,
-    } = class
-    {
-        if let Some(fun) = methods.get(name) {
-            return Some(fun.clone());
+pub fn binary_op(
+    left: &LiteralValue,
+    operator: TokenType,
+    right: &LiteralValue,
+) -> Result<LiteralValue, String> {
+    match (left, operator, right) {
+        (Number(x), TokenType::Plus, Number(y)) => Ok(Number(x + y)),
+        (Number(x), TokenType::Minus, Number(y)) => Ok(Number(x - y)),
+        (Number(x), TokenType::Star, Number(y)) => Ok(Number(x * y)),
+        (Number(x), TokenType::Slash, Number(y)) => Ok(Number(x / y)),
+        (Number(x), TokenType::Greater, Number(y)) => Ok(LiteralValue::from_bool(x > y)),
+        (Number(x), TokenType::GreaterEqual, Number(y)) => Ok(LiteralValue::from_bool(x >= y)),
+        (Number(x), TokenType::Less, Number(y)) => Ok(LiteralValue::from_bool(x < y)),
+        (Number(x), TokenType::LessEqual, Number(y)) => Ok(LiteralValue::from_bool(x <= y)),
+
+        (StringValue(_), op, Number(_)) => {
+            Err(format!("{} is not defined for string and number", op))
         }
-        if let Some(superclass) = superclass {
-            return find_method(name, *superclass.clone());
+        (Number(_), op, StringValue(_)) => {
+            Err(format!("{} is not defined for string and number", op))
         }
-        None
-    } else {
-        panic!("Cannot find method on non-class");
+
+        (StringValue(s1), TokenType::Plus, StringValue(s2)) => {
+            Ok(StringValue(format!("{}{}", s1, s2)))
+        }
+
+        (x, TokenType::BangEqual, y) => Ok(LiteralValue::from_bool(x != y)),
+        (x, TokenType::EqualEqual, y) => Ok(LiteralValue::from_bool(x == y)),
+        (StringValue(s1), TokenType::Greater, StringValue(s2)) => {
+            Ok(LiteralValue::from_bool(s1 > s2))
+        }
+        (StringValue(s1), TokenType::GreaterEqual, StringValue(s2)) => {
+            Ok(LiteralValue::from_bool(s1 >= s2))
+        }
+        (StringValue(s1), TokenType::Less, StringValue(s2)) => Ok(LiteralValue::from_bool(s1 < s2)),
+        (StringValue(s1), TokenType::LessEqual, StringValue(s2)) => {
+            Ok(LiteralValue::from_bool(s1 <= s2))
+        }
+        (x, ttype, y) => Err(format!(
+            "{} is not implemented for operands {:?} and {:?}",
+            ttype, x, y
+        )),
     }
 }
 
@@ -877,6 +1442,8 @@ mod tests {
             lexeme: "-".to_string(),
             literal: None,
             line_number: 0,
+            offset: 0,
+            column: 0,
         };
         let onetwothree = Literal {
             id: 0,
@@ -894,6 +1461,8 @@ mod tests {
             lexeme: "*".to_string(),
             literal: None,
             line_number: 0,
+            offset: 0,
+            column: 0,
         };
         let ast = Binary {
             id: 3,
@@ -918,6 +1487,8 @@ mod tests {
             lexeme: "-".to_string(),
             literal: None,
             line_number: 0,
+            offset: 0,
+            column: 0,
         };
         let onetwothree = Literal {
             id: 0,
@@ -935,6 +1506,8 @@ mod tests {
             lexeme: "*".to_string(),
             literal: None,
             line_number: 0,
+            offset: 0,
+            column: 0,
         };
         let expr = Binary {
             id: 3,
@@ -950,8 +1523,38 @@ mod tests {
         let addr = std::ptr::addr_of!(expr) as usize;
         locals.insert(addr, 0);
 
-        if let None = locals.get(&addr) {
+        if !locals.contains_key(&addr) {
             panic!("Failed");
         }
     }
+
+    #[test]
+    fn runtime_error_records_calls() {
+        use crate::parser::Parser;
+        use crate::resolver::Resolver;
+        use crate::scanner::Scanner;
+
+        let source = "class Box { init(v) { this.v = check(v); } }\n\
+                      fun check(v) { return v + nil; }\n\
+                      fun make() { return Box(1); }\n\
+                      make();";
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        let locals = Resolver::new().resolve(&stmts.iter().collect()).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.resolve(locals);
+
+        let error = match interpreter.interpret(stmts.iter().collect()) {
+            Err(error) => error,
+            Ok(_) => panic!("Expected a runtime error"),
+        };
+        let calls: Vec<(&str, usize)> = error
+            .trace
+            .iter()
+            .map(|frame| (frame.function.as_str(), frame.call_site.line))
+            .collect();
+        assert_eq!(calls, vec![("check", 1), ("Box", 3), ("make", 4)]);
+        // The stack is unwound once the error has been reported
+        assert!(interpreter.environment.call_stack().is_empty());
+    }
 }
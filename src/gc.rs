@@ -0,0 +1,226 @@
+//! A cycle collector for the values of moss programs. Values are reference
+//! counted, which frees everything except cycles: an instance that stores one
+//! of its own methods, or a closure that refers to itself through a captured
+//! variable. Every object that can take part in a cycle is tracked here
+//! weakly, and a collection finds the ones that are only kept alive by other
+//! tracked objects and clears them, which breaks their cycles.
+//!
+//! Collections run by themselves as programs allocate, or when a program
+//! calls `gc()`. The heap belongs to the thread, so every program on a thread
+//! shares it.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+// Tracked objects allocated before the first automatic collection. Later
+// ones wait until as many objects were allocated as survived the last one.
+const FIRST_COLLECTION: usize = 10_000;
+
+/// A heap object that holds references to other heap objects
+pub trait Trace {
+    /// Calls `visit` with the address of every tracked object this one holds
+    /// a strong reference to, once per reference. Returns false without
+    /// visiting anything if the object is in use and can't be read.
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool;
+
+    /// Drops the references this object holds, as far as it can be changed
+    fn clear(&self);
+}
+
+/// Counts of the objects the cycle collector knows about on this thread
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HeapStats {
+    /// Objects tracked so far
+    pub allocated: usize,
+    /// Tracked objects that are still alive
+    pub live: usize,
+    /// Collections run so far
+    pub collections: usize,
+    /// Objects freed by collections so far
+    pub freed: usize,
+}
+
+#[derive(Default)]
+struct Heap {
+    // By address, which only one live object can have
+    objects: HashMap<*const (), Weak<dyn Trace>>,
+    // Objects allocated since the last collection
+    allocated: usize,
+    // Objects that survived the last collection
+    survivors: usize,
+    stats: HeapStats,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap::default());
+}
+
+/// Tracks an object that can be part of a cycle. An object that is already
+/// tracked is not tracked again.
+pub fn track<T: Trace + 'static>(object: &Rc<T>) {
+    let due = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        let key = address(object);
+        let tracked = heap.objects.get(&key);
+        if tracked.is_some_and(|tracked| tracked.strong_count() > 0) {
+            return false;
+        }
+        let object: Weak<T> = Rc::downgrade(object);
+        heap.objects.insert(key, object);
+        heap.allocated += 1;
+        heap.stats.allocated += 1;
+        heap.allocated > FIRST_COLLECTION.max(heap.survivors)
+    });
+    if due {
+        collect_garbage();
+    }
+}
+
+/// The address of an object, as `Trace::trace` reports it
+pub fn address<T: ?Sized>(object: &Rc<T>) -> *const () {
+    Rc::as_ptr(object) as *const ()
+}
+
+/// The counts of the heap of this thread
+pub fn heap_stats() -> HeapStats {
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        HeapStats {
+            live: heap
+                .objects
+                .values()
+                .filter(|o| o.strong_count() > 0)
+                .count(),
+            ..heap.stats
+        }
+    })
+}
+
+/// Frees the objects of this thread that are only alive because they are
+/// part of a cycle
+pub fn collect_garbage() -> HeapStats {
+    let objects: Vec<Rc<dyn Trace>> = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.objects.retain(|_, o| o.strong_count() > 0);
+        heap.allocated = 0;
+        heap.objects.values().filter_map(Weak::upgrade).collect()
+    });
+
+    let index: HashMap<*const (), usize> = objects
+        .iter()
+        .enumerate()
+        .map(|(i, object)| (address(object), i))
+        .collect();
+
+    // The references to each object from outside the tracked objects. This
+    // collection holds one of them.
+    let mut outside: Vec<isize> = objects
+        .iter()
+        .map(|o| Rc::strong_count(o) as isize - 1)
+        .collect();
+    let mut readable = vec![true; objects.len()];
+    for (i, object) in objects.iter().enumerate() {
+        readable[i] = object.trace(&mut |child| {
+            if let Some(&j) = index.get(&child) {
+                outside[j] -= 1;
+            }
+        });
+    }
+
+    // Whatever something outside refers to is alive, and so is everything it
+    // reaches. An object that can't be read counts as referred to, and keeps
+    // alive what it refers to since those references were not subtracted.
+    let mut alive = vec![false; objects.len()];
+    let mut pending: Vec<usize> = (0..objects.len())
+        .filter(|&i| outside[i] > 0 || !readable[i])
+        .collect();
+    while let Some(i) = pending.pop() {
+        if alive[i] {
+            continue;
+        }
+        alive[i] = true;
+        objects[i].trace(&mut |child| {
+            if let Some(&j) = index.get(&child) {
+                pending.push(j);
+            }
+        });
+    }
+
+    let mut freed = 0;
+    for (object, alive) in objects.iter().zip(&alive) {
+        if !alive {
+            object.clear();
+            freed += 1;
+        }
+    }
+    let survivors = objects.len() - freed;
+    drop(objects);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.survivors = survivors;
+        heap.stats.collections += 1;
+        heap.stats.freed += freed;
+    });
+    heap_stats()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Node {
+        next: RefCell<Option<Rc<Node>>>,
+    }
+
+    impl Trace for Node {
+        fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+            match self.next.try_borrow() {
+                Ok(next) => {
+                    next.iter().for_each(|next| visit(address(next)));
+                    true
+                }
+                Err(_) => false,
+            }
+        }
+
+        fn clear(&self) {
+            self.next.take();
+        }
+    }
+
+    fn node() -> Rc<Node> {
+        let node = Rc::new(Node {
+            next: RefCell::new(None),
+        });
+        track(&node);
+        node
+    }
+
+    #[test]
+    fn cycles_are_freed_once_nothing_else_refers_to_them() {
+        let (a, b) = (node(), node());
+        *a.next.borrow_mut() = Some(b.clone());
+        *b.next.borrow_mut() = Some(a.clone());
+        let weak = Rc::downgrade(&b);
+        drop(b);
+
+        collect_garbage();
+        assert!(weak.upgrade().is_some());
+
+        drop(a);
+        let freed = heap_stats().freed;
+        assert_eq!(collect_garbage().freed, freed + 2);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn objects_are_tracked_once() {
+        let a = node();
+        let stats = heap_stats();
+        track(&a);
+        track(&a);
+        assert_eq!(heap_stats(), stats);
+    }
+}
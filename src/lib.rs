@@ -0,0 +1,31 @@
+//! moss as a library. A host creates a [`Moss`] program, runs source code in
+//! it, calls its functions and exchanges values with it as [`LiteralValue`]s.
+
+mod chunk;
+mod compiler;
+mod convert;
+mod embed;
+mod environment;
+mod error;
+mod expr;
+mod gc;
+mod interpreter;
+mod module;
+mod output;
+mod parser;
+mod process;
+mod repl;
+mod resolver;
+mod scanner;
+mod stdlib;
+mod stmt;
+mod tests;
+mod vm;
+
+pub use embed::{Backend, Moss};
+pub use error::{ErrorKind, Frame, MossError, Span, SCRIPT};
+pub use expr::LiteralValue;
+pub use gc::{collect_garbage, heap_stats, HeapStats};
+pub use output::{Buffer, Output};
+pub use process::{ProcessPolicy, Programs};
+pub use repl::{ast, complete_statement, is_incomplete, tokens};
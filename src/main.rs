@@ -1,103 +1,238 @@
-mod environment;
-mod expr;
-mod interpreter;
-mod parser;
-mod resolver;
-mod scanner;
-mod stmt;
-mod tests;
-use crate::interpreter::*;
-use crate::parser::*;
-use crate::resolver::*;
-use crate::scanner::*;
+use moss::{
+    ast, complete_statement, is_incomplete, tokens, Backend, ErrorKind, LiteralValue, Moss,
+    MossError, ProcessPolicy, SCRIPT,
+};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
 use std::env;
 use std::fs;
-use std::io::{self, BufRead, Write};
+use std::path::Path;
 use std::process::exit;
 
-pub fn run_file(path: &str) -> Result<(), String> {
-    // let mut interpreter = Interpreter::new();
-    match fs::read_to_string(path) {
-        Err(msg) => return Err(msg.to_string()),
-        Ok(contents) => return run_string(&contents),
+// Lines typed at the prompt are kept in this file in the home directory
+const HISTORY_FILE: &str = ".moss_history";
+
+/// How a program is run, and what it is allowed to do
+pub struct Options {
+    pub backend: Backend,
+    pub process: ProcessPolicy,
+    pub filesystem: bool,
+    pub deny_warnings: bool,
+}
+
+impl Options {
+    fn moss(&self) -> Moss {
+        let mut moss = Moss::new(self.backend).with_process_policy(self.process.clone());
+        if !self.filesystem {
+            moss = moss.without_filesystem();
+        }
+        if self.deny_warnings {
+            moss = moss.deny_warnings();
+        }
+        moss
+    }
+
+    // A program that reports the warnings of the source it runs before
+    // running it. Denied warnings are reported with the errors instead.
+    fn program(&self, file: &str) -> Moss {
+        let file = file.to_string();
+        self.moss().with_warnings(move |warnings, source| {
+            let warnings: Vec<MossError> = warnings.into_iter().map(|w| w.in_file(&file)).collect();
+            report(&warnings, &file, source);
+        })
     }
 }
 
-pub fn run_string(contents: &str) -> Result<(), String> {
-    let mut interpreter = Interpreter::new();
+pub fn run_file(path: &str, options: &Options) -> Result<(), Vec<MossError>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|msg| MossError::new(ErrorKind::Io, msg.to_string(), None).in_file(path))?;
+    // Imports are relative to the directory of the file
+    let result = options.program(path).in_file(path).eval(&contents);
+    result.map(|_| ()).map_err(|errors| {
+        let errors: Vec<MossError> = errors.into_iter().map(|e| e.in_file(path)).collect();
+        report(&errors, path, &contents);
+        errors
+    })
+}
 
-    run(&mut interpreter, contents)
+pub fn run_string(contents: &str, options: &Options) -> Result<(), Vec<MossError>> {
+    options.program(SCRIPT).eval(contents).map(|_| ())
 }
 
-fn run(interpreter: &mut Interpreter, contents: &str) -> Result<(), String> {
-    let mut scanner = Scanner::new(contents);
-    let tokens = scanner.scan_tokens()?;
+fn run_prompt(options: &Options) -> Result<(), String> {
+    let mut moss = options.program(SCRIPT);
+    let mut editor =
+        DefaultEditor::new().map_err(|e| format!("Could not start the prompt: {}", e))?;
+    let history = env::var_os("HOME").map(|home| Path::new(&home).join(HISTORY_FILE));
+    if let Some(path) = &history {
+        // There is no history yet the first time
+        let _ = editor.load_history(path);
+    }
 
-    let mut parser = Parser::new(tokens);
-    let stmts = parser.parse()?;
+    let mut source = String::new();
+    loop {
+        let prompt = if source.is_empty() { "> " } else { "... " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl-C drops the lines typed so far
+            Err(ReadlineError::Interrupted) => {
+                source.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(format!("Could not read line: {}", e)),
+        };
+        if source.is_empty() && line.trim().is_empty() {
+            continue;
+        }
 
-    let resolver = Resolver::new();
-    let locals = resolver.resolve(&stmts.iter().collect())?;
+        source.push_str(&line);
+        source.push('\n');
+        if is_incomplete(&source) {
+            continue;
+        }
 
-    interpreter.resolve(locals);
+        let input = std::mem::take(&mut source);
+        let _ = editor.add_history_entry(input.trim_end());
+        match input.trim().strip_prefix(':') {
+            Some(command) => meta_command(&mut moss, command),
+            None => evaluate(&mut moss, &input, SCRIPT),
+        }
+    }
 
-    interpreter.interpret(stmts.iter().collect())?;
-    return Ok(());
+    if let Some(path) = &history {
+        editor
+            .save_history(path)
+            .map_err(|e| format!("Could not save the history: {}", e))?;
+    }
+    Ok(())
 }
 
-fn run_prompt() -> Result<(), String> {
-    let mut interpreter = Interpreter::new();
-    loop {
-        print!("> ");
-        match io::stdout().flush() {
-            Ok(_) => (),
-            Err(_) => return Err("Could not flush stdout".to_string()),
+// Runs a line of the prompt and shows the value of a bare expression. The
+// semicolon after the last statement may be left out.
+fn evaluate(moss: &mut Moss, input: &str, file: &str) {
+    let source = complete_statement(input);
+
+    match moss.eval(&source) {
+        Ok(Some(LiteralValue::Nil)) | Ok(None) => (),
+        Ok(Some(value)) => println!("{}", value.to_string()),
+        Err(errors) => {
+            for error in errors {
+                print!("{}", render(&error, file, &source));
+            }
         }
+    }
+}
 
-        let mut buffer = String::new();
-        let stdin = io::stdin();
-        let mut handle = stdin.lock();
-        match handle.read_line(&mut buffer) {
-            Ok(n) => {
-                if n == 0 {
-                    println!("");
-                    return Ok(());
-                } else if n == 1 {
-                    continue;
-                }
+fn meta_command(moss: &mut Moss, command: &str) {
+    let (name, argument) = command
+        .split_once(char::is_whitespace)
+        .unwrap_or((command, ""));
+    let argument = argument.trim();
+    let lines = match name {
+        "env" => Ok(moss.dump_globals().lines().map(String::from).collect()),
+        "ast" => ast(argument),
+        "tokens" => tokens(argument),
+        "load" => {
+            match fs::read_to_string(argument) {
+                Ok(contents) => evaluate(moss, &contents, argument),
+                Err(e) => println!("Could not read file '{}': {}", argument, e),
             }
-            Err(_) => return Err("Couldnt read line".to_string()),
+            return;
+        }
+        _ => {
+            println!(
+                "Unknown command :{}, the commands are :env, :ast <code>, :tokens <code> and :load <file>",
+                name
+            );
+            return;
         }
+    };
+
+    match lines {
+        Ok(lines) => lines.iter().for_each(|line| println!("{}", line)),
+        Err(errors) => {
+            for error in errors {
+                print!("{}", render(&error, SCRIPT, argument));
+            }
+        }
+    }
+}
+
+/// Prints the located diagnostics to stderr. Only the bare messages go to
+/// stdout, where the test cases compare them.
+fn report(errors: &[MossError], file: &str, source: &str) {
+    for error in errors {
+        eprint!("{}", render(error, file, source));
+    }
+}
 
-        println!("ECHO: {}", buffer);
-        match run(&mut interpreter, &buffer) {
-            Ok(_) => (),
-            Err(msg) => println!("{}", msg),
+// Renders an error of the program in `file`. Errors raised in an imported
+// module point into that module's source instead.
+fn render(error: &MossError, file: &str, source: &str) -> String {
+    match &error.file {
+        Some(module) if module != file => {
+            error.render(&fs::read_to_string(module).unwrap_or_default())
         }
+        _ => error.render(source),
     }
 }
 
+fn messages(errors: &[MossError]) -> String {
+    errors
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+
+    let mut options = Options {
+        backend: Backend::TreeWalker,
+        process: ProcessPolicy::default(),
+        filesystem: true,
+        deny_warnings: false,
+    };
+    while args.len() > 1 && args[1].starts_with("--") {
+        let flag = args.remove(1);
+        if flag == "--vm" {
+            options.backend = Backend::Vm;
+        } else if flag == "--no-commands" {
+            options.process = ProcessPolicy::deny_all();
+        } else if let Some(programs) = flag.strip_prefix("--allow-commands=") {
+            options.process =
+                ProcessPolicy::allow_only(programs.split(',').filter(|p| !p.is_empty()));
+        } else if flag == "--no-files" {
+            options.filesystem = false;
+        } else if flag == "--deny-warnings" {
+            options.deny_warnings = true;
+        } else {
+            println!("Unknown option {}", flag);
+            exit(64);
+        }
+    }
 
     if args.len() == 2 {
-        match run_file(&args[1]) {
+        match run_file(&args[1], &options) {
             Ok(_) => exit(0),
-            Err(msg) => {
-                println!("ERROR:\n{}", msg);
+            Err(errors) => {
+                println!("ERROR:\n{}", messages(&errors));
                 exit(1);
             }
         }
     } else if args.len() == 3 && args[1] == "e" {
-        match run_string(&args[2]) {
+        match run_string(&args[2], &options) {
             Ok(_) => exit(0),
-            Err(msg) => {
-                println!("ERROR:\n{msg}");
+            Err(errors) => {
+                report(&errors, SCRIPT, &args[2]);
+                println!("ERROR:\n{}", messages(&errors));
                 exit(1);
             }
         }
     } else if args.len() == 1 {
-        match run_prompt() {
+        match run_prompt(&options) {
             Ok(_) => exit(0),
             Err(msg) => {
                 println!("ERROR\n{}", msg);
@@ -105,7 +240,7 @@ fn main() {
             }
         }
     } else {
-        println!("Usage: jlox [script]");
+        println!("Usage: moss [--vm] [--no-commands | --allow-commands=a,b] [--no-files] [--deny-warnings] [script]");
         exit(64);
     }
 }
@@ -0,0 +1,137 @@
+use crate::error::{ErrorKind, MossError, Span};
+use crate::expr::LiteralValue;
+use crate::gc::Trace;
+use crate::parser::Parser;
+use crate::resolver::{Locals, Resolver};
+use crate::scanner::Scanner;
+use crate::stmt::Stmt;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A module that has been read and resolved, ready to be run by a backend
+pub struct Module {
+    // The path of the module as it was imported, relative to the working directory
+    pub file: String,
+    pub stmts: Vec<Stmt>,
+    pub locals: Locals,
+}
+
+/// The modules loaded by a program. A module runs once, the first time it is
+/// imported, and every import of it gets the same exports.
+#[derive(Default)]
+pub struct Modules {
+    cache: RefCell<HashMap<PathBuf, LiteralValue>>,
+    // The modules that are being run, outermost first
+    loading: RefCell<Vec<PathBuf>>,
+}
+
+impl Modules {
+    /// Returns the exports of the module at `path`, which is relative to the
+    /// directory of the importing file `from`. `run` runs the module in its
+    /// own globals and collects its exports the first time it is imported.
+    pub fn import(
+        &self,
+        path: &str,
+        from: &str,
+        span: Span,
+        run: impl FnOnce(Module) -> Result<LiteralValue, MossError>,
+    ) -> Result<LiteralValue, MossError> {
+        let error = |message: String| MossError::new(ErrorKind::Runtime, message, Some(span));
+
+        let dir = Path::new(from).parent().unwrap_or(Path::new(""));
+        let file = dir.join(path);
+        let key = file
+            .canonicalize()
+            .map_err(|e| error(format!("Could not import '{}': {}", path, e)))?;
+
+        if let Some(exports) = self.cache.borrow().get(&key) {
+            return Ok(exports.clone());
+        }
+
+        if let Some(start) = self.loading.borrow().iter().position(|p| *p == key) {
+            let cycle: Vec<String> = self.loading.borrow()[start..]
+                .iter()
+                .chain([&key])
+                .map(|p| {
+                    p.file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect();
+            return Err(error(format!("Import cycle: {}", cycle.join(" -> "))));
+        }
+
+        let file = file.display().to_string();
+        let source = fs::read_to_string(&key)
+            .map_err(|e| error(format!("Could not import '{}': {}", path, e)))?;
+        let module = parse(file.clone(), &source).map_err(|e| e.in_file(&file))?;
+
+        self.loading.borrow_mut().push(key.clone());
+        let exports = run(module);
+        self.loading.borrow_mut().pop();
+        let exports = exports.map_err(|e| e.in_file(&file))?;
+
+        self.cache.borrow_mut().insert(key, exports.clone());
+        Ok(exports)
+    }
+}
+
+// Only the first error of a module is reported, like a runtime error
+fn parse(file: String, source: &str) -> Result<Module, MossError> {
+    let first = |mut errors: Vec<MossError>| errors.remove(0);
+
+    let tokens = Scanner::new(source).scan_tokens().map_err(first)?;
+    let stmts = Parser::new(tokens).parse().map_err(first)?;
+    let locals = Resolver::new().resolve(&stmts.iter().collect())?;
+
+    Ok(Module {
+        file,
+        stmts,
+        locals,
+    })
+}
+
+// The exports of loaded modules keep the functions in them alive, and those
+// functions keep the modules of their program alive
+impl Trace for Modules {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        match self.cache.try_borrow() {
+            Ok(cache) => {
+                cache.values().for_each(|exports| exports.trace(visit));
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut cache) = self.cache.try_borrow_mut() {
+            cache.clear();
+        }
+    }
+}
+
+/// The exports of a module that has finished running, as a map from each
+/// exported name to the value `lookup` finds for it in the module's globals
+pub fn exports(
+    stmts: &[Stmt],
+    lookup: impl Fn(&str) -> Option<LiteralValue>,
+) -> Result<LiteralValue, MossError> {
+    let mut exports = HashMap::new();
+    for stmt in stmts {
+        if let Stmt::Export { name } = stmt {
+            let value = lookup(&name.lexeme).ok_or_else(|| {
+                MossError::runtime(
+                    format!("Cannot export undefined name '{}'", name.lexeme),
+                    name,
+                )
+            })?;
+            exports.insert(name.lexeme.clone(), value);
+        }
+    }
+
+    Ok(LiteralValue::map(exports))
+}
@@ -0,0 +1,61 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// Where `print` writes. Every environment of a program, and the modules it
+/// imports, share one output, which is stdout unless the host sets another.
+#[derive(Clone)]
+pub struct Output(Rc<RefCell<dyn Write>>);
+
+impl Output {
+    pub fn new(writer: impl Write + 'static) -> Self {
+        Self(Rc::new(RefCell::new(writer)))
+    }
+
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+
+    /// Discards everything that is printed
+    pub fn sink() -> Self {
+        Self::new(io::sink())
+    }
+
+    /// Writes `line` and a newline
+    pub fn print(&self, line: &str) -> Result<(), String> {
+        writeln!(self.0.borrow_mut(), "{}", line)
+            .map_err(|e| format!("Could not write output: {}", e))
+    }
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self::stdout()
+    }
+}
+
+/// An output that collects what is printed in memory. Clones share the same
+/// buffer, so a host can keep one and hand the other to a program.
+#[derive(Clone, Default)]
+pub struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+impl Buffer {
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+}
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
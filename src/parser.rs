@@ -1,4 +1,6 @@
+use crate::error::MossError;
 use crate::expr::{Expr, Expr::*, LiteralValue};
+use crate::process::split_command;
 use crate::scanner::{Token, TokenType, TokenType::*};
 use crate::stmt::Stmt;
 
@@ -30,7 +32,7 @@ impl Parser {
         id
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, String> {
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<MossError>> {
         let mut stmts = vec![];
         let mut errs = vec![];
 
@@ -45,26 +47,32 @@ impl Parser {
             }
         }
 
-        if errs.len() == 0 {
+        if errs.is_empty() {
             Ok(stmts)
         } else {
-            Err(errs.join("\n"))
+            Err(errs)
         }
     }
 
-    fn declaration(&mut self) -> Result<Stmt, String> {
+    fn declaration(&mut self) -> Result<Stmt, MossError> {
         if self.match_token(Var) {
             self.var_declaration()
         } else if self.match_token(Fun) {
             self.function(FunctionKind::Function)
         } else if self.match_token(Class) {
             self.class_declaration()
+        } else if self.match_token(Import) {
+            self.import_declaration()
+        } else if self.match_token(Export) {
+            let name = self.consume(Identifier, "Expected name after 'export'.")?;
+            self.consume(Semicolon, "Expected ';' after export.")?;
+            Ok(Stmt::Export { name })
         } else {
             self.statement()
         }
     }
 
-    fn class_declaration(&mut self) -> Result<Stmt, String> {
+    fn class_declaration(&mut self) -> Result<Stmt, MossError> {
         let name = self.consume(Identifier, "Expected name after 'class' keyword.")?;
         let superclass = if self.match_token(TokenType::Less) {
             self.consume(Identifier, "Expected superclass name after '<'.")?;
@@ -93,17 +101,11 @@ impl Parser {
         })
     }
 
-    fn function(&mut self, kind: FunctionKind) -> Result<Stmt, String> {
+    fn function(&mut self, kind: FunctionKind) -> Result<Stmt, MossError> {
         let name = self.consume(Identifier, &format!("Expected {kind:?} name"))?;
 
         if self.match_token(Gets) {
-            let cmd_body = self.consume(StringLit, "Expected command body")?; 
-            self.consume(Semicolon, "Expected ';' after command body")?;
-
-            return Ok(Stmt::CmdFunction {
-                name,
-                cmd: cmd_body.lexeme,
-            });
+            return self.command(name, vec![]);
         }
 
         self.consume(LeftParen, &format!("Expected '(' after {kind:?} name"))?;
@@ -112,9 +114,9 @@ impl Parser {
         if !self.check(RightParen) {
             loop {
                 if parameters.len() >= 255 {
-                    let location = self.peek().line_number;
-                    return Err(format!(
-                        "Line {location}: Cant have more than 255 arguments"
+                    return Err(MossError::parse(
+                        "Cant have more than 255 arguments",
+                        &self.peek(),
                     ));
                 }
 
@@ -128,6 +130,10 @@ impl Parser {
         }
         self.consume(RightParen, "Expected ')' after parameters.")?;
 
+        if self.match_token(Gets) {
+            return self.command(name, parameters);
+        }
+
         self.consume(LeftBrace, &format!("Expected '{{' before {kind:?} body."))?;
         let body = match self.block_statement()? {
             Stmt::Block { statements } => statements,
@@ -141,18 +147,57 @@ impl Parser {
         })
     }
 
-    fn var_declaration(&mut self) -> Result<Stmt, String> {
+    // The command of `fun name(params) <- "command";`, after the `<-`
+    fn command(&mut self, name: Token, params: Vec<Token>) -> Result<Stmt, MossError> {
+        let command = self.consume(StringLit, "Expected command body")?;
+        let argv = match &command.literal {
+            Some(crate::scanner::LiteralValue::StringValue(s)) => {
+                split_command(s).map_err(|msg| MossError::parse(msg, &command))?
+            }
+            _ => panic!("String token without a string literal"),
+        };
+        self.consume(Semicolon, "Expected ';' after command body")?;
+
+        Ok(Stmt::CmdFunction { name, params, argv })
+    }
+
+    fn import_declaration(&mut self) -> Result<Stmt, MossError> {
+        let keyword = self.previous();
+        let name = self.consume(Identifier, "Expected module name after 'import'.")?;
+
+        // `from` is only a keyword here, it can still name variables
+        let from = self.peek();
+        if from.token_type != Identifier || from.lexeme != "from" {
+            return Err(MossError::parse("Expected 'from' after module name.", &from));
+        }
+        self.advance();
+
+        let path = self.consume(StringLit, "Expected module path after 'from'.")?;
+        self.consume(Semicolon, "Expected ';' after import.")?;
+
+        let path = match path.literal {
+            Some(crate::scanner::LiteralValue::StringValue(path)) => path,
+            _ => panic!("String token without a string literal"),
+        };
+
+        Ok(Stmt::Import {
+            keyword,
+            name,
+            path,
+        })
+    }
+
+    fn var_declaration(&mut self) -> Result<Stmt, MossError> {
         let token = self.consume(Identifier, "Expected variable name")?;
 
-        let initializer;
-        if self.match_token(Equal) {
-            initializer = self.expression()?;
+        let initializer = if self.match_token(Equal) {
+            self.expression()?
         } else {
-            initializer = Literal {
+            Literal {
                 id: self.get_id(),
                 value: LiteralValue::Nil,
-            };
-        }
+            }
+        };
 
         self.consume(Semicolon, "Expected ';' after variable declaration")?;
 
@@ -162,7 +207,7 @@ impl Parser {
         })
     }
 
-    fn statement(&mut self) -> Result<Stmt, String> {
+    fn statement(&mut self) -> Result<Stmt, MossError> {
         if self.match_token(Print) {
             self.print_statement()
         } else if self.match_token(LeftBrace) {
@@ -175,26 +220,33 @@ impl Parser {
             self.for_statement()
         } else if self.match_token(Return) {
             self.return_statement()
+        } else if self.match_token(Break) {
+            let keyword = self.previous();
+            self.consume(Semicolon, "Expected ';' after 'break'.")?;
+            Ok(Stmt::Break { keyword })
+        } else if self.match_token(Continue) {
+            let keyword = self.previous();
+            self.consume(Semicolon, "Expected ';' after 'continue'.")?;
+            Ok(Stmt::Continue { keyword })
         } else {
             self.expression_statement()
         }
     }
 
-    fn return_statement(&mut self) -> Result<Stmt, String> {
+    fn return_statement(&mut self) -> Result<Stmt, MossError> {
         let keyword = self.previous();
-        let value;
-        if !self.check(Semicolon) {
+        let value = if !self.check(Semicolon) {
             // NOT return;
-            value = Some(self.expression()?);
+            Some(self.expression()?)
         } else {
-            value = None;
-        }
+            None
+        };
         self.consume(Semicolon, "Expected ';' after return value;")?;
 
         Ok(Stmt::ReturnStmt { keyword, value })
     }
 
-    fn for_statement(&mut self) -> Result<Stmt, String> {
+    fn for_statement(&mut self) -> Result<Stmt, MossError> {
         // for v
         //       ( SMTH ; SMTH ; SMTH )
         self.consume(LeftParen, "Expected '(' after 'for'.")?;
@@ -212,48 +264,33 @@ impl Parser {
         }
 
         // Consumes "SMTH? ;"
-        let condition;
-        if !self.check(Semicolon) {
-            let expr = self.expression()?;
-            condition = Some(expr);
+        let condition = if !self.check(Semicolon) {
+            Some(self.expression()?)
         } else {
-            condition = None;
-        }
+            None
+        };
         self.consume(Semicolon, "Expected ';' after loop condition.")?;
 
-        let increment;
-        if !self.check(RightParen) {
-            let expr = self.expression()?;
-            increment = Some(expr);
+        let increment = if !self.check(RightParen) {
+            Some(self.expression()?)
         } else {
-            increment = None;
-        }
+            None
+        };
         self.consume(RightParen, "Expected ')' after for clauses.")?;
 
-        let mut body = self.statement()?;
-
-        if let Some(incr) = increment {
-            body = Stmt::Block {
-                statements: vec![
-                    Box::new(body),
-                    Box::new(Stmt::Expression { expression: incr }),
-                ],
-            };
-        }
+        let body = self.statement()?;
 
-        let cond;
-        match condition {
-            None => {
-                cond = Expr::Literal {
-                    id: self.get_id(),
-                    value: LiteralValue::True,
-                }
-            }
-            Some(c) => cond = c,
-        }
-        body = Stmt::WhileStmt {
+        let cond = match condition {
+            None => Expr::Literal {
+                id: self.get_id(),
+                value: LiteralValue::True,
+            },
+            Some(c) => c,
+        };
+        let mut body = Stmt::WhileStmt {
             condition: cond,
             body: Box::new(body),
+            increment,
         };
 
         if let Some(init) = initializer {
@@ -265,7 +302,7 @@ impl Parser {
         Ok(body)
     }
 
-    fn while_statement(&mut self) -> Result<Stmt, String> {
+    fn while_statement(&mut self) -> Result<Stmt, MossError> {
         self.consume(LeftParen, "Expected '(' after 'while'")?;
         let condition = self.expression()?;
         self.consume(RightParen, "Expected ')' after condition.")?;
@@ -274,10 +311,11 @@ impl Parser {
         Ok(Stmt::WhileStmt {
             condition,
             body: Box::new(body),
+            increment: None,
         })
     }
 
-    fn if_statement(&mut self) -> Result<Stmt, String> {
+    fn if_statement(&mut self) -> Result<Stmt, MossError> {
         self.consume(LeftParen, "Expected '(' after 'if'")?;
         let predicate = self.expression()?;
         self.consume(RightParen, "Expected ')' after if-predicate")?;
@@ -297,7 +335,7 @@ impl Parser {
         })
     }
 
-    fn block_statement(&mut self) -> Result<Stmt, String> {
+    fn block_statement(&mut self) -> Result<Stmt, MossError> {
         let mut statements = vec![];
 
         while !self.check(RightBrace) && !self.is_at_end() {
@@ -309,31 +347,31 @@ impl Parser {
         Ok(Stmt::Block { statements })
     }
 
-    fn print_statement(&mut self) -> Result<Stmt, String> {
+    fn print_statement(&mut self) -> Result<Stmt, MossError> {
         let value = self.expression()?;
         self.consume(Semicolon, "Expected ';' after value.")?;
         Ok(Stmt::Print { expression: value })
     }
 
-    fn expression_statement(&mut self) -> Result<Stmt, String> {
+    fn expression_statement(&mut self) -> Result<Stmt, MossError> {
         let expr = self.expression()?;
         self.consume(Semicolon, "Expected ';' after expression.")?;
         Ok(Stmt::Expression { expression: expr })
     }
 
-    fn expression(&mut self) -> Result<Expr, String> {
+    fn expression(&mut self) -> Result<Expr, MossError> {
         self.assignment()
     }
 
-    fn function_expression(&mut self) -> Result<Expr, String> {
+    fn function_expression(&mut self) -> Result<Expr, MossError> {
         let paren = self.consume(LeftParen, "Expected '(' after anonymous function")?;
         let mut parameters = vec![];
         if !self.check(RightParen) {
             loop {
                 if parameters.len() >= 255 {
-                    let location = self.peek().line_number;
-                    return Err(format!(
-                        "Line {location}: Cant have more than 255 arguments"
+                    return Err(MossError::parse(
+                        "Cant have more than 255 arguments",
+                        &self.peek(),
                     ));
                 }
 
@@ -368,11 +406,12 @@ impl Parser {
         })
     }
 
-    fn assignment(&mut self) -> Result<Expr, String> {
+    fn assignment(&mut self) -> Result<Expr, MossError> {
         // a = 2; NOT var a = 2;
         let expr = self.pipe()?; // a |> f = 2;
 
         if self.match_token(Equal) {
+            let equals = self.previous();
             let value = self.expression()?;
 
             match expr {
@@ -391,14 +430,60 @@ impl Parser {
                     name,
                     value: Box::new(value),
                 }),
-                _ => Err("Invalid assignment target.".to_string()),
+                Index {
+                    id: _,
+                    object,
+                    bracket,
+                    index,
+                } => Ok(IndexSet {
+                    id: self.get_id(),
+                    object,
+                    bracket,
+                    index,
+                    value: Box::new(value),
+                }),
+                _ => Err(MossError::parse("Invalid assignment target.", &equals)),
             }
+        } else if self.match_tokens(&[PlusEqual, MinusEqual, StarEqual, SlashEqual]) {
+            // a += 2;
+            let operator = self.previous();
+            let value = self.expression()?;
+            self.update(expr, operator, value, false)
         } else {
             Ok(expr)
         }
     }
 
-    fn pipe(&mut self) -> Result<Expr, String> {
+    fn update(
+        &mut self,
+        target: Expr,
+        operator: Token,
+        value: Expr,
+        postfix: bool,
+    ) -> Result<Expr, MossError> {
+        match target {
+            Variable { .. } | Get { .. } | Index { .. } => Ok(Update {
+                id: self.get_id(),
+                target: Box::new(target),
+                operator,
+                value: Box::new(value),
+                postfix,
+            }),
+            _ => Err(MossError::parse(
+                format!("Invalid target for '{}'.", operator.lexeme),
+                &operator,
+            )),
+        }
+    }
+
+    fn one(&mut self) -> Expr {
+        Literal {
+            id: self.get_id(),
+            value: LiteralValue::Number(1.0),
+        }
+    }
+
+    fn pipe(&mut self) -> Result<Expr, MossError> {
         // expr |> f
         // expr |> f1 |> f2
         // expr |> (f1 |> f2)
@@ -422,7 +507,7 @@ impl Parser {
         Ok(expr)
     }
 
-    fn or(&mut self) -> Result<Expr, String> {
+    fn or(&mut self) -> Result<Expr, MossError> {
         let mut expr = self.and()?;
 
         while self.match_token(Or) {
@@ -440,7 +525,7 @@ impl Parser {
         Ok(expr)
     }
 
-    fn and(&mut self) -> Result<Expr, String> {
+    fn and(&mut self) -> Result<Expr, MossError> {
         let mut expr = self.equality()?;
 
         while self.match_token(And) {
@@ -457,7 +542,7 @@ impl Parser {
         Ok(expr)
     }
 
-    fn equality(&mut self) -> Result<Expr, String> {
+    fn equality(&mut self) -> Result<Expr, MossError> {
         let mut expr = self.comparison()?;
         while self.match_tokens(&[BangEqual, EqualEqual]) {
             let operator = self.previous();
@@ -473,7 +558,7 @@ impl Parser {
         Ok(expr)
     }
 
-    fn comparison(&mut self) -> Result<Expr, String> {
+    fn comparison(&mut self) -> Result<Expr, MossError> {
         let mut expr = self.term()?;
 
         while self.match_tokens(&[Greater, GreaterEqual, Less, LessEqual]) {
@@ -490,7 +575,7 @@ impl Parser {
         Ok(expr)
     }
 
-    fn term(&mut self) -> Result<Expr, String> {
+    fn term(&mut self) -> Result<Expr, MossError> {
         let mut expr = self.factor()?;
 
         while self.match_tokens(&[Minus, Plus]) {
@@ -507,7 +592,7 @@ impl Parser {
         Ok(expr)
     }
 
-    fn factor(&mut self) -> Result<Expr, String> {
+    fn factor(&mut self) -> Result<Expr, MossError> {
         let mut expr = self.unary()?;
         while self.match_tokens(&[Slash, Star]) {
             let op = self.previous();
@@ -523,7 +608,7 @@ impl Parser {
         Ok(expr)
     }
 
-    fn unary(&mut self) -> Result<Expr, String> {
+    fn unary(&mut self) -> Result<Expr, MossError> {
         if self.match_tokens(&[Bang, Minus]) {
             let op = self.previous();
             let rhs = self.unary()?;
@@ -532,12 +617,26 @@ impl Parser {
                 operator: op,
                 right: Box::from(rhs),
             })
+        } else if self.match_tokens(&[PlusPlus, MinusMinus]) {
+            // ++i
+            let op = self.previous();
+            let target = self.unary()?;
+            let one = self.one();
+            self.update(target, op, one, false)
         } else {
-            self.call()
+            let expr = self.call()?;
+            if self.match_tokens(&[PlusPlus, MinusMinus]) {
+                // i++
+                let op = self.previous();
+                let one = self.one();
+                self.update(expr, op, one, true)
+            } else {
+                Ok(expr)
+            }
         }
     }
 
-    fn call(&mut self) -> Result<Expr, String> {
+    fn call(&mut self) -> Result<Expr, MossError> {
         let mut expr = self.primary()?;
 
         loop {
@@ -550,6 +649,15 @@ impl Parser {
                     object: Box::new(expr),
                     name,
                 };
+            } else if self.match_token(LeftBracket) {
+                let index = self.expression()?;
+                let bracket = self.consume(RightBracket, "Expected ']' after index.")?;
+                expr = Index {
+                    id: self.get_id(),
+                    object: Box::new(expr),
+                    bracket,
+                    index: Box::new(index),
+                };
             } else {
                 break;
             }
@@ -558,7 +666,7 @@ impl Parser {
         Ok(expr)
     }
 
-    fn finish_call(&mut self, callee: Expr) -> Result<Expr, String> {
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, MossError> {
         let mut arguments = vec![];
 
         if !self.check(RightParen) {
@@ -566,9 +674,9 @@ impl Parser {
                 let arg = self.expression()?;
                 arguments.push(arg);
                 if arguments.len() >= 255 {
-                    let location = self.peek().line_number;
-                    return Err(format!(
-                        "Line {location}: Cant have more than 255 arguments"
+                    return Err(MossError::parse(
+                        "Cant have more than 255 arguments",
+                        &self.peek(),
                     ));
                 }
 
@@ -587,39 +695,38 @@ impl Parser {
         })
     }
 
-    fn primary(&mut self) -> Result<Expr, String> {
+    fn primary(&mut self) -> Result<Expr, MossError> {
         let token = self.peek();
-        let result;
-        match token.token_type {
+        let result = match token.token_type {
             LeftParen => {
                 self.advance();
                 let expr = self.expression()?;
                 self.consume(RightParen, "Expected ')'")?;
-                result = Grouping {
+                Grouping {
                     id: self.get_id(),
                     expression: Box::from(expr),
-                };
+                }
             }
             False | True | Nil | Number | StringLit => {
                 self.advance();
-                result = Literal {
+                Literal {
                     id: self.get_id(),
                     value: LiteralValue::from_token(token),
                 }
             }
             Identifier => {
                 self.advance();
-                result = Variable {
+                Variable {
                     id: self.get_id(),
                     name: self.previous(),
-                };
+                }
             }
             TokenType::This => {
                 self.advance();
-                result = Expr::This {
+                Expr::This {
                     id: self.get_id(),
                     keyword: token,
-                };
+                }
             }
             TokenType::Super => {
                 // Should always occur with a method call
@@ -627,30 +734,74 @@ impl Parser {
                 self.consume(TokenType::Dot, "Expected '.' after 'super'.")?;
                 let method =
                     self.consume(TokenType::Identifier, "Expected superclass method name.")?;
-                result = Expr::Super {
+                Expr::Super {
                     id: self.get_id(),
                     keyword: token,
                     method,
-                };
+                }
             }
             Fun => {
                 self.advance();
-                result = self.function_expression()?;
+                self.function_expression()?
             }
-            _ => return Err("Expected expression".to_string()),
-        }
+            LeftBracket => {
+                self.advance();
+                let mut elements = vec![];
+                while !self.check(RightBracket) && !self.is_at_end() {
+                    elements.push(self.expression()?);
+                    if !self.match_token(Comma) {
+                        break;
+                    }
+                }
+                self.consume(RightBracket, "Expected ']' after array elements.")?;
+                Array {
+                    id: self.get_id(),
+                    elements,
+                }
+            }
+            HashLeftBrace => {
+                self.advance();
+                let mut entries = vec![];
+                while !self.check(RightBrace) && !self.is_at_end() {
+                    let key = match self.peek().token_type {
+                        Identifier => self.advance().lexeme,
+                        StringLit => match self.advance().literal {
+                            Some(crate::scanner::LiteralValue::StringValue(s)) => s,
+                            _ => unreachable!(),
+                        },
+                        _ => {
+                            return Err(MossError::parse(
+                                "Expected identifier or string as object key.",
+                                &self.peek(),
+                            ));
+                        }
+                    };
+                    self.consume(Colon, "Expected ':' after object key.")?;
+                    entries.push((key, self.expression()?));
+                    if !self.match_token(Comma) {
+                        break;
+                    }
+                }
+                self.consume(RightBrace, "Expected '}' after object members.")?;
+                Object {
+                    id: self.get_id(),
+                    entries,
+                }
+            }
+            _ => return Err(MossError::parse("Expected expression", &token)),
+        };
 
         Ok(result)
     }
 
-    fn consume(&mut self, token_type: TokenType, msg: &str) -> Result<Token, String> {
+    fn consume(&mut self, token_type: TokenType, msg: &str) -> Result<Token, MossError> {
         let token = self.peek();
         if token.token_type == token_type {
             self.advance();
             let token = self.previous();
             Ok(token)
         } else {
-            Err(format!("Line {}: {}", token.line_number, msg))
+            Err(MossError::parse(msg, &token))
         }
     }
 
@@ -731,30 +882,40 @@ mod tests {
             lexeme: "1".to_string(),
             literal: Some(FValue(1.0)),
             line_number: 0,
+            offset: 0,
+            column: 0,
         };
         let plus = Token {
             token_type: Plus,
             lexeme: "+".to_string(),
             literal: None,
             line_number: 0,
+            offset: 0,
+            column: 0,
         };
         let two = Token {
             token_type: Number,
             lexeme: "2".to_string(),
             literal: Some(FValue(2.0)),
             line_number: 0,
+            offset: 0,
+            column: 0,
         };
         let semicol = Token {
             token_type: Semicolon,
             lexeme: ";".to_string(),
             literal: None,
             line_number: 0,
+            offset: 0,
+            column: 0,
         };
         let eof = Token {
             token_type: Eof,
             lexeme: "".to_string(),
             literal: None,
             line_number: 0,
+            offset: 0,
+            column: 0,
         };
 
         let tokens = vec![one, plus, two, semicol, eof];
@@ -789,6 +950,70 @@ mod tests {
 
         assert_eq!(string_expr, "(== 1 (group (+ 2 2)))");
     }
+
+    #[test]
+    fn test_array_index_set() {
+        let source = "a[0] = [1, b[2]];";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let parsed_expr = parser.parse().unwrap();
+        let string_expr = parsed_expr[0].to_string();
+
+        assert_eq!(string_expr, "(index-set (var a) 0 (array 1 (index (var b) 2)))");
+    }
+
+    #[test]
+    fn test_update_operators() {
+        let source = "a.b += -c++ * --d[0];";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let parsed_expr = parser.parse().unwrap();
+        let string_expr = parsed_expr[0].to_string();
+
+        assert_eq!(
+            string_expr,
+            "(+= (get (var a) b) (* (- (post++ (var c))) (-- (index (var d) 0))))"
+        );
+    }
+
+    #[test]
+    fn test_object_literal() {
+        let source = "#{a: 1, \"b c\": #{},};";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let parsed_expr = parser.parse().unwrap();
+        let string_expr = parsed_expr[0].to_string();
+
+        assert_eq!(string_expr, "(object (a 1) (b c (object)))");
+    }
+
+    #[test]
+    fn test_import_export() {
+        let source = "import lib from \"lib.moss\"; var from = 1; export from;";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let parsed = parser.parse().unwrap();
+        let strings: Vec<String> = parsed.iter().map(|stmt| stmt.to_string()).collect();
+
+        assert_eq!(strings, vec!["(import lib \"lib.moss\")", "(var from)", "(export from)"]);
+    }
+    #[test]
+    fn test_command_function() {
+        let source = "fun find(pattern, file) <- \"grep -n 'two words'\";";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let parsed = parser.parse().unwrap();
+
+        assert_eq!(
+            parsed[0].to_string(),
+            "(cmd find (pattern file) [\"grep\", \"-n\", \"two words\"])"
+        );
+    }
     #[test]
     fn test_function_decl(){
         let source = r#"fun foo(a, b) {
@@ -0,0 +1,306 @@
+use crate::expr::{CallableImpl, LiteralValue, NativeFunctionImpl};
+use crate::stdlib::argument_error;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::process::{Child, Command as Process, Stdio};
+use std::rc::Rc;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Which programs a script may run with `fun name <- "command";`, and for how
+/// long. Hosts that embed moss can forbid spawning processes altogether.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessPolicy {
+    pub programs: Programs,
+    // A command still running after this long is killed
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Programs {
+    Any,
+    // Programs are matched by the name written in the command
+    Only(HashSet<String>),
+    None,
+}
+
+impl Default for ProcessPolicy {
+    fn default() -> Self {
+        Self {
+            programs: Programs::Any,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ProcessPolicy {
+    pub fn deny_all() -> Self {
+        Self {
+            programs: Programs::None,
+            ..Self::default()
+        }
+    }
+
+    pub fn allow_only<S: Into<String>>(programs: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            programs: Programs::Only(programs.into_iter().map(Into::into).collect()),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    fn check(&self, program: &str) -> Result<(), String> {
+        match &self.programs {
+            Programs::Any => Ok(()),
+            Programs::Only(allowed) if allowed.contains(program) => Ok(()),
+            Programs::Only(_) => Err(format!("Running '{}' is not allowed", program)),
+            Programs::None => Err("Running commands is disabled".to_string()),
+        }
+    }
+}
+
+/// A function declared with `fun name(params) <- "command";`. Calling it
+/// runs the command with the arguments of the call appended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Command {
+    pub name: String,
+    pub argv: Vec<String>,
+    pub arity: usize,
+}
+
+impl Command {
+    /// The callable for this command, which runs it under `policy`
+    pub fn native(&self, policy: Rc<ProcessPolicy>) -> LiteralValue {
+        let command = self.clone();
+        let fun = move |args: &[LiteralValue]| {
+            let mut argv = command.argv.clone();
+            for (index, arg) in args.iter().enumerate() {
+                argv.push(match arg {
+                    LiteralValue::StringValue(s) => s.clone(),
+                    LiteralValue::Number(x) => x.to_string(),
+                    other => {
+                        return Err(argument_error(
+                            &command.name,
+                            index,
+                            "a String or Number",
+                            other,
+                        ))
+                    }
+                });
+            }
+            run(&argv, &policy)
+        };
+
+        LiteralValue::Callable(CallableImpl::NativeFunction(NativeFunctionImpl {
+            name: self.name.clone(),
+            arity: self.arity,
+            fun: Rc::new(fun),
+        }))
+    }
+}
+
+/// Splits a command into the program and its arguments. Words are separated
+/// by whitespace, single quotes keep everything between them as is and a
+/// backslash escapes the next character.
+pub fn split_command(command: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("Unterminated quote in command".to_string()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err("Command ends with a lone '\\'".to_string()),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+
+    if words.is_empty() {
+        return Err("Command is empty".to_string());
+    }
+    Ok(words)
+}
+
+/// Runs `argv` and returns a map with its exit `status` (nil when it was
+/// killed by a signal), `stdout` and `stderr`
+pub fn run(argv: &[String], policy: &ProcessPolicy) -> Result<LiteralValue, String> {
+    let program = &argv[0];
+    policy.check(program)?;
+
+    let mut child = Process::new(program)
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run command '{}': {}", program, e))?;
+
+    // The pipes are drained while waiting, so that a command with a lot of
+    // output does not block on a full pipe
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let deadline = Instant::now() + policy.timeout;
+    let timed_out = || {
+        format!(
+            "Command '{}' timed out after {}s",
+            program,
+            policy.timeout.as_secs_f64()
+        )
+    };
+    let status = wait(&mut child, deadline).map_err(|e| match e {
+        Some(e) => format!("Failed to run command '{}': {}", program, e),
+        None => timed_out(),
+    })?;
+    // Something the command started in the background can keep its pipes
+    // open after it exited
+    let stdout = collect(stdout, deadline).ok_or_else(timed_out)?;
+    let stderr = collect(stderr, deadline).ok_or_else(timed_out)?;
+
+    let mut result = HashMap::new();
+    result.insert(
+        "status".to_string(),
+        match status.code() {
+            Some(code) => LiteralValue::Number(code as f64),
+            None => LiteralValue::Nil,
+        },
+    );
+    result.insert("stdout".to_string(), LiteralValue::StringValue(stdout));
+    result.insert("stderr".to_string(), LiteralValue::StringValue(stderr));
+    Ok(LiteralValue::map(result))
+}
+
+// Reads a pipe to its end on another thread, which sends what it read once
+// every process holding the pipe closed it
+fn drain(pipe: Option<impl Read + Send + 'static>) -> mpsc::Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut bytes = vec![];
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        let _ = sender.send(String::from_utf8_lossy(&bytes).into_owned());
+    });
+    receiver
+}
+
+// What a drained pipe held, or None if it was still open at `deadline`. The
+// thread reading it is left behind until it closes.
+fn collect(output: mpsc::Receiver<String>, deadline: Instant) -> Option<String> {
+    match output.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(output) => Some(output),
+        Err(RecvTimeoutError::Disconnected) => Some(String::new()),
+        Err(RecvTimeoutError::Timeout) => None,
+    }
+}
+
+// Waits for `child` to exit, killing it once `deadline` has passed. The error
+// is None when the command timed out.
+fn wait(
+    child: &mut Child,
+    deadline: Instant,
+) -> Result<std::process::ExitStatus, Option<std::io::Error>> {
+    loop {
+        if let Some(status) = child.try_wait().map_err(Some)? {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(None);
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(command: &str) -> Vec<String> {
+        split_command(command).unwrap()
+    }
+
+    fn field(result: &LiteralValue, name: &str) -> LiteralValue {
+        match result {
+            LiteralValue::Map(entries) => entries.borrow()[name].clone(),
+            _ => panic!("Expected a map"),
+        }
+    }
+
+    #[test]
+    fn split_command_handles_quotes() {
+        assert_eq!(words("echo  hello world"), ["echo", "hello", "world"]);
+        assert_eq!(words("grep 'a b' file"), ["grep", "a b", "file"]);
+        assert_eq!(words("echo it\\'s ''"), ["echo", "it's", ""]);
+        assert_eq!(words("echo a'b c'd"), ["echo", "ab cd"]);
+        assert!(split_command("echo 'open").is_err());
+        assert!(split_command("  ").is_err());
+    }
+
+    #[test]
+    fn policy_limits_programs() {
+        let argv = words("echo hi");
+        let only_true = ProcessPolicy::allow_only(["true"]);
+        assert_eq!(
+            run(&argv, &only_true),
+            Err("Running 'echo' is not allowed".to_string())
+        );
+        assert_eq!(
+            run(&argv, &ProcessPolicy::deny_all()),
+            Err("Running commands is disabled".to_string())
+        );
+
+        let result = run(&argv, &ProcessPolicy::allow_only(["echo"])).unwrap();
+        assert_eq!(
+            field(&result, "stdout"),
+            LiteralValue::StringValue("hi\n".to_string())
+        );
+    }
+
+    #[test]
+    fn captures_status_and_stderr() {
+        let result = run(&words("sh -c 'echo oops >&2; exit 3'"), &Default::default()).unwrap();
+        assert_eq!(field(&result, "status"), LiteralValue::Number(3.0));
+        assert_eq!(
+            field(&result, "stderr"),
+            LiteralValue::StringValue("oops\n".to_string())
+        );
+    }
+
+    #[test]
+    fn slow_commands_time_out() {
+        let policy = ProcessPolicy::default().with_timeout(Duration::from_millis(100));
+        let start = Instant::now();
+        assert_eq!(
+            run(&words("sleep 5"), &policy),
+            Err("Command 'sleep' timed out after 0.1s".to_string())
+        );
+
+        let started = Instant::now();
+        assert_eq!(
+            run(&words("sh -c 'sleep 5 & echo hi'"), &policy),
+            Err("Command 'sh' timed out after 0.1s".to_string())
+        );
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,101 @@
+//! What an interactive prompt needs besides running code: telling whether the
+//! input typed so far is a whole program, finishing its last statement, and
+//! showing how moss reads it.
+
+use crate::error::MossError;
+use crate::parser::Parser;
+use crate::scanner::{Scanner, TokenType};
+
+/// The tokens of `source`, one per line as the scanner describes them
+pub fn tokens(source: &str) -> Result<Vec<String>, Vec<MossError>> {
+    let tokens = Scanner::new(source).scan_tokens()?;
+    Ok(tokens.iter().map(|token| token.to_string()).collect())
+}
+
+/// The statements of `source` as s-expressions
+pub fn ast(source: &str) -> Result<Vec<String>, Vec<MossError>> {
+    let tokens = Scanner::new(source).scan_tokens()?;
+    let stmts = Parser::new(tokens).parse()?;
+    Ok(stmts.iter().map(|stmt| stmt.to_string()).collect())
+}
+
+/// Whether `source` stops inside a string or before closing a bracket, so
+/// more lines are needed before it can run. Any other error is left for
+/// running it to report.
+pub fn is_incomplete(source: &str) -> bool {
+    let tokens = match Scanner::new(source).scan_tokens() {
+        Ok(tokens) => tokens,
+        Err(errors) => {
+            return errors
+                .iter()
+                .any(|error| error.message == "Unterminated string")
+        }
+    };
+
+    let mut depth = 0;
+    for token in tokens {
+        match token.token_type {
+            TokenType::LeftParen
+            | TokenType::LeftBrace
+            | TokenType::LeftBracket
+            | TokenType::HashLeftBrace => depth += 1,
+            TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => depth -= 1,
+            _ => (),
+        }
+    }
+    depth > 0
+}
+
+/// `source` with the `;` its last statement may leave out at the prompt.
+/// Source that doesn't parse either way is returned as it is, for running it
+/// to report the error.
+pub fn complete_statement(source: &str) -> String {
+    let source = source.trim_end();
+    let parses = |source: &str| {
+        Scanner::new(source)
+            .scan_tokens()
+            .is_ok_and(|tokens| Parser::new(tokens).parse().is_ok())
+    };
+    if !parses(source) && parses(&format!("{};", source)) {
+        return format!("{};", source);
+    }
+    source.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_brackets_and_strings_are_incomplete() {
+        assert!(is_incomplete("fun f() {"));
+        assert!(is_incomplete("fun f() {\n  print [1,"));
+        assert!(is_incomplete("var m = #{ a: (1"));
+        assert!(is_incomplete("print \"two\nlines"));
+
+        assert!(!is_incomplete("fun f() { return 1; }"));
+        assert!(!is_incomplete("print 1 +"));
+        assert!(!is_incomplete("}"));
+    }
+
+    #[test]
+    fn the_last_semicolon_can_be_left_out() {
+        assert_eq!(complete_statement("1 + 2"), "1 + 2;");
+        assert_eq!(complete_statement("#{b: 2}"), "#{b: 2};");
+        assert_eq!(complete_statement("print #{a: 1}\n"), "print #{a: 1};");
+        assert_eq!(complete_statement("var a = 1"), "var a = 1;");
+
+        assert_eq!(complete_statement("fun f() { return 1; }"), "fun f() { return 1; }");
+        assert_eq!(complete_statement("print 1;"), "print 1;");
+        assert_eq!(complete_statement("print 1 +"), "print 1 +");
+    }
+
+    #[test]
+    fn show_the_syntax_tree() {
+        assert_eq!(
+            ast("fun f(a, b) { if (a) return b; }").unwrap(),
+            vec!["(fun f (a b) (if (var a) (return (var b))))"]
+        );
+        assert!(ast("var = 1;").is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::error::{ErrorKind, MossError};
 use crate::expr::Expr;
 use crate::scanner::Token;
 use crate::stmt::Stmt;
@@ -8,25 +9,107 @@ enum FunctionType {
     None,
     Function,
     Method,
+    Initializer,
+}
+
+// The class whose methods are being resolved, which decides whether `this`
+// and `super` mean anything
+#[derive(Copy, Clone, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+/// Where a variable lives, as seen from the code that refers to it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Slot {
+    // In a scope of the running function, `depth` scopes out
+    Local { depth: usize, slot: usize },
+    // Among the variables the running function captured
+    Upvalue(usize),
+}
+
+/// A variable a function captures when it is created, found from the scope
+/// it is defined in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    Local { depth: usize, slot: usize },
+    // A variable the enclosing function captured itself
+    Upvalue(usize),
+}
+
+/// What the interpreter needs to find variables without looking up names.
+/// Anything missing from `variables` is a global.
+#[derive(Debug, Default)]
+pub struct Locals {
+    // By the id of each variable, assignment, `this` and `super` expression
+    pub variables: HashMap<usize, Slot>,
+    // The `this` each `super` expression calls its method on, by its id
+    pub receivers: HashMap<usize, Slot>,
+    // The captures of each function, by the offset of its name (the `(` of an
+    // anonymous function)
+    pub captures: HashMap<usize, Vec<Capture>>,
+}
+
+// A function whose body is being resolved
+struct FunctionScope {
+    // The index of its outermost scope, the one holding its parameters
+    base: usize,
+    captures: Vec<Capture>,
+}
+
+// A name declared in a local scope
+struct Local {
+    // Its position in the scope, where the interpreter keeps its value
+    slot: usize,
+    defined: bool,
+    used: bool,
+    // What warnings call the name, and where it was declared. The implicit
+    // `this` and `super` are never reported.
+    declaration: Option<(&'static str, Token)>,
+}
+
+impl Local {
+    // `super` is alone in its scope and `this` comes before the parameters
+    fn implicit() -> Self {
+        Self {
+            slot: 0,
+            defined: true,
+            used: true,
+            declaration: None,
+        }
+    }
 }
 
-#[allow(dead_code)]
 pub struct Resolver {
-    scopes: Vec<HashMap<String, bool>>,
+    scopes: Vec<HashMap<String, Local>>,
+    functions: Vec<FunctionScope>,
     current_function: FunctionType,
-    locals: HashMap<usize, usize>,
+    current_class: ClassType,
+    loop_depth: usize,
+    locals: Locals,
+    warnings: Vec<MossError>,
+    // The line each global declared so far is declared on, for shadowing
+    // warnings
+    globals: HashMap<String, usize>,
 }
 
 impl Resolver {
     pub fn new() -> Self {
         Self {
             scopes: vec![],
+            functions: vec![],
             current_function: FunctionType::None,
-            locals: HashMap::new(),
+            current_class: ClassType::None,
+            loop_depth: 0,
+            locals: Locals::default(),
+            warnings: vec![],
+            globals: HashMap::new(),
         }
     }
 
-    fn resolve_internal(&mut self, stmt: &Stmt) -> Result<(), String> {
+    fn resolve_internal(&mut self, stmt: &Stmt) -> Result<(), MossError> {
         match stmt {
             Stmt::Block { statements: _ } => self.resolve_block(stmt)?,
             Stmt::Var {
@@ -38,6 +121,16 @@ impl Resolver {
                 methods,
                 superclass,
             } => {
+                let enclosing_class = self.current_class;
+                self.current_class = match superclass {
+                    Some(_) => ClassType::Subclass,
+                    None => ClassType::Class,
+                };
+
+                // Resolving class. Its methods can refer to it.
+                self.declare(name, "Local class")?;
+                self.define(name);
+
                 // Resolve superclass, if present
                 if let Some(super_expr) = superclass {
                     if let Expr::Variable {
@@ -46,7 +139,10 @@ impl Resolver {
                     } = super_expr
                     {
                         if super_name.lexeme == name.lexeme {
-                            return Err("A class cannot inherit from itself".to_string());
+                            return Err(MossError::resolve(
+                                "A class cannot inherit from itself",
+                                super_name,
+                            ));
                         }
                     }
 
@@ -55,35 +151,31 @@ impl Resolver {
                     self.scopes
                         .last_mut()
                         .unwrap()
-                        .insert("super".to_string(), true);
+                        .insert("super".to_string(), Local::implicit());
                 }
 
-                // Resolving class
-                self.declare(name)?;
-                self.define(name);
-
                 // Resolving methods
-                self.begin_scope();
-                self.scopes
-                    .last_mut()
-                    .unwrap()
-                    .insert("this".to_string(), true);
                 for method in methods {
-                    let declaration = FunctionType::Method;
+                    let declaration = match method.as_ref() {
+                        Stmt::Function { name, .. } if name.lexeme == "init" => {
+                            FunctionType::Initializer
+                        }
+                        _ => FunctionType::Method,
+                    };
                     self.resolve_function(method, declaration)?;
                 }
-                self.end_scope();
 
                 if superclass.is_some() {
                     self.end_scope();
                 }
+                self.current_class = enclosing_class;
             }
             Stmt::Function {
                 name: _,
                 params: _,
                 body: _,
             } => self.resolve_function(stmt, FunctionType::Function)?,
-            Stmt::CmdFunction { name: _, cmd: _ } => self.resolve_var(stmt)?,
+            Stmt::CmdFunction { name: _, params: _, argv: _ } => self.resolve_var(stmt)?,
             Stmt::Expression { expression } => self.resolve_expr(expression)?,
             Stmt::IfStmt {
                 predicate: _,
@@ -91,37 +183,130 @@ impl Resolver {
                 els: _,
             } => self.resolve_if_stmt(stmt)?,
             Stmt::Print { expression } => self.resolve_expr(expression)?,
-            Stmt::ReturnStmt { keyword: _, value } => {
+            Stmt::ReturnStmt { keyword, value } => {
                 if self.current_function == FunctionType::None {
-                    return Err("Return statement is not allowed outside of a function".to_string());
+                    self.warn(
+                        "'return' outside of a function ends the program".to_string(),
+                        keyword,
+                    );
                 }
 
                 if let Some(value) = value {
+                    if self.current_function == FunctionType::Initializer {
+                        return Err(MossError::resolve(
+                            "Cannot return a value from an initializer",
+                            keyword,
+                        ));
+                    }
                     self.resolve_expr(value)?;
                 }
             }
-            Stmt::WhileStmt { condition, body } => {
+            Stmt::WhileStmt {
+                condition,
+                body,
+                increment,
+            } => {
                 self.resolve_expr(condition)?;
+                self.loop_depth += 1;
                 self.resolve_internal(body.as_ref())?;
+                self.loop_depth -= 1;
+                if let Some(increment) = increment {
+                    self.resolve_expr(increment)?;
+                }
+            }
+            Stmt::Break { keyword } => {
+                if self.loop_depth == 0 {
+                    return Err(MossError::resolve(
+                        "Break statement is not allowed outside of a loop",
+                        keyword,
+                    ));
+                }
+            }
+            Stmt::Continue { keyword } => {
+                if self.loop_depth == 0 {
+                    return Err(MossError::resolve(
+                        "Continue statement is not allowed outside of a loop",
+                        keyword,
+                    ));
+                }
+            }
+            // Modules are loaded and their exports collected once, so both only
+            // make sense among the top level statements
+            Stmt::Import {
+                keyword,
+                name,
+                path: _,
+            } => {
+                if !self.scopes.is_empty() {
+                    return Err(MossError::resolve(
+                        "Import is only allowed at the top level of a module",
+                        keyword,
+                    ));
+                }
+                self.declare(name, "Module")?;
+            }
+            Stmt::Export { name } => {
+                if !self.scopes.is_empty() {
+                    return Err(MossError::resolve(
+                        "Export is only allowed at the top level of a module",
+                        name,
+                    ));
+                }
             }
         }
         Ok(())
     }
 
-    fn resolve_many(&mut self, stmts: &Vec<&Stmt>) -> Result<(), String> {
-        for stmt in stmts {
+    fn resolve_many(&mut self, stmts: &Vec<&Stmt>) -> Result<(), MossError> {
+        let mut unreachable = false;
+        for (i, stmt) in stmts.iter().enumerate() {
             self.resolve_internal(stmt)?;
+
+            let keyword = match stmt {
+                Stmt::ReturnStmt { keyword, value: _ }
+                | Stmt::Break { keyword }
+                | Stmt::Continue { keyword } => Some(keyword),
+                _ => None,
+            };
+            if let Some(keyword) = keyword {
+                if i + 1 < stmts.len() && !unreachable {
+                    unreachable = true;
+                    self.warn(
+                        format!("Code after '{}' is unreachable", keyword.lexeme),
+                        keyword,
+                    );
+                }
+            }
         }
 
         Ok(())
     }
 
-    pub fn resolve(mut self, stmts: &Vec<&Stmt>) -> Result<HashMap<usize, usize>, String> {
+    pub fn resolve(self, stmts: &Vec<&Stmt>) -> Result<Locals, MossError> {
+        self.resolve_with_warnings(stmts).map(|(locals, _)| locals)
+    }
+
+    /// Resolves the program and also returns what looks like a mistake but
+    /// does not stop it from running, in source order
+    pub fn resolve_with_warnings(
+        mut self,
+        stmts: &Vec<&Stmt>,
+    ) -> Result<(Locals, Vec<MossError>), MossError> {
         self.resolve_many(stmts)?;
-        Ok(self.locals)
+        self.warnings
+            .sort_by_key(|warning| warning.span.map(|span| span.offset));
+        Ok((self.locals, self.warnings))
+    }
+
+    fn warn(&mut self, message: String, token: &Token) {
+        self.warnings.push(MossError::new(
+            ErrorKind::Warning,
+            message,
+            Some(token.span()),
+        ));
     }
 
-    fn resolve_block(&mut self, stmt: &Stmt) -> Result<(), String> {
+    fn resolve_block(&mut self, stmt: &Stmt) -> Result<(), MossError> {
         match stmt {
             Stmt::Block { statements } => {
                 self.begin_scope();
@@ -134,13 +319,13 @@ impl Resolver {
         Ok(())
     }
 
-    fn resolve_var(&mut self, stmt: &Stmt) -> Result<(), String> {
+    fn resolve_var(&mut self, stmt: &Stmt) -> Result<(), MossError> {
         if let Stmt::Var { name, initializer } = stmt {
-            self.declare(name)?;
+            self.declare(name, "Local variable")?;
             self.resolve_expr(initializer)?;
             self.define(name);
-        } else if let Stmt::CmdFunction {name, cmd: _} = stmt {
-            self.declare(name)?;
+        } else if let Stmt::CmdFunction {name, params: _, argv: _} = stmt {
+            self.declare(name, "Local function")?;
             self.define(name);
         } else {
             panic!("Wrong type in resolve var");
@@ -149,12 +334,16 @@ impl Resolver {
         Ok(())
     }
 
-    fn resolve_function(&mut self, stmt: &Stmt, fn_type: FunctionType) -> Result<(), String> {
+    fn resolve_function(&mut self, stmt: &Stmt, fn_type: FunctionType) -> Result<(), MossError> {
         if let Stmt::Function { name, params, body } = stmt {
-            self.declare(name)?;
-            self.define(name);
+            // Methods are found on the instance, not in a scope
+            if fn_type == FunctionType::Function {
+                self.declare(name, "Local function")?;
+                self.define(name);
+            }
 
             self.resolve_function_helper(
+                name,
                 params,
                 &body.iter().map(|b| b.as_ref()).collect(),
                 fn_type,
@@ -164,7 +353,7 @@ impl Resolver {
         }
     }
 
-    fn resolve_if_stmt(&mut self, stmt: &Stmt) -> Result<(), String> {
+    fn resolve_if_stmt(&mut self, stmt: &Stmt) -> Result<(), MossError> {
         if let Stmt::IfStmt {
             predicate,
             then,
@@ -183,22 +372,43 @@ impl Resolver {
         }
     }
 
+    // `token` is the name of the function, or the `(` of an anonymous one
     fn resolve_function_helper(
         &mut self,
+        token: &Token,
         params: &Vec<Token>,
         body: &Vec<&Stmt>,
         resolving_function: FunctionType,
-    ) -> Result<(), String> {
+    ) -> Result<(), MossError> {
         let enclosing_function = self.current_function;
         self.current_function = resolving_function;
+        // A loop around the function does not make break or continue valid inside it
+        let enclosing_loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+        self.functions.push(FunctionScope {
+            base: self.scopes.len(),
+            captures: vec![],
+        });
         self.begin_scope();
+        // A method finds its instance in the slot before its parameters
+        if matches!(
+            resolving_function,
+            FunctionType::Method | FunctionType::Initializer
+        ) {
+            self.scopes
+                .last_mut()
+                .unwrap()
+                .insert("this".to_string(), Local::implicit());
+        }
         for param in params {
-            self.declare(param)?;
+            self.declare(param, "Parameter")?;
             self.define(param);
         }
         self.resolve_many(body)?;
         self.end_scope();
+        let function = self.functions.pop().expect("Function stack underflow");
+        self.locals.captures.insert(token.offset, function.captures);
         self.current_function = enclosing_function;
+        self.loop_depth = enclosing_loop_depth;
         Ok(())
     }
 
@@ -206,21 +416,59 @@ impl Resolver {
         self.scopes.push(HashMap::new());
     }
 
+    // Names starting with `_` are meant to be unused
     fn end_scope(&mut self) {
-        self.scopes.pop().expect("Stack underflow");
+        let scope = self.scopes.pop().expect("Stack underflow");
+        for (name, local) in scope {
+            if let Some((kind, token)) = &local.declaration {
+                if !local.used && !name.starts_with('_') {
+                    self.warn(format!("{} '{}' is never used", kind, name), token);
+                }
+            }
+        }
     }
 
-    fn declare(&mut self, name: &Token) -> Result<(), String> {
+    // `kind` is what warnings call the name
+    fn declare(&mut self, name: &Token, kind: &'static str) -> Result<(), MossError> {
         let size = self.scopes.len();
         if self.scopes.is_empty() {
+            self.globals
+                .entry(name.lexeme.clone())
+                .or_insert(name.line_number);
             return Ok(());
         }
 
         if self.scopes[size - 1].contains_key(&name.lexeme.clone()) {
-            return Err("A variable with this name is already in scope".to_string());
+            return Err(MossError::resolve(
+                "A variable with this name is already in scope",
+                name,
+            ));
+        }
+
+        let shadowed = self.scopes[..size - 1]
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.lexeme)?.declaration.as_ref())
+            .map(|(_, outer)| outer.line_number)
+            .or_else(|| self.globals.get(&name.lexeme).copied());
+        if let Some(line) = shadowed {
+            let message = format!(
+                "'{}' shadows the variable declared on line {}",
+                name.lexeme, line
+            );
+            self.warn(message, name);
         }
 
-        self.scopes[size - 1].insert(name.lexeme.clone(), false);
+        let slot = self.scopes[size - 1].len();
+        self.scopes[size - 1].insert(
+            name.lexeme.clone(),
+            Local {
+                slot,
+                defined: false,
+                used: false,
+                declaration: Some((kind, name.clone())),
+            },
+        );
 
         Ok(())
     }
@@ -231,7 +479,9 @@ impl Resolver {
         }
 
         let size = self.scopes.len();
-        self.scopes[size - 1].insert(name.lexeme.clone(), true);
+        if let Some(local) = self.scopes[size - 1].get_mut(&name.lexeme) {
+            local.defined = true;
+        }
     }
 
     // (i > j) may require different resolution distances
@@ -240,7 +490,7 @@ impl Resolver {
     // (i > 3) ->
     //         -> i -> try to resolve
     //         -> 3 -> try to resolve (trivial)
-    fn resolve_expr(&mut self, expr: &Expr) -> Result<(), String> {
+    fn resolve_expr(&mut self, expr: &Expr) -> Result<(), MossError> {
         match expr {
             Expr::Variable { id: _, name: _ } => self.resolve_expr_var(expr, expr.get_id()),
             Expr::Assign {
@@ -276,7 +526,55 @@ impl Resolver {
                 name: _,
             } => self.resolve_expr(object),
             Expr::Grouping { id: _, expression } => self.resolve_expr(expression),
+            Expr::Array { id: _, elements } => {
+                for element in elements {
+                    self.resolve_expr(element)?;
+                }
+
+                Ok(())
+            }
+            Expr::Index {
+                id: _,
+                object,
+                bracket: _,
+                index,
+            } => {
+                self.resolve_expr(object)?;
+                self.resolve_expr(index)
+            }
+            Expr::IndexSet {
+                id: _,
+                object,
+                bracket: _,
+                index,
+                value,
+            } => {
+                self.resolve_expr(object)?;
+                self.resolve_expr(index)?;
+                self.resolve_expr(value)
+            }
             Expr::Literal { id: _, value: _ } => Ok(()),
+            Expr::Update {
+                id: _,
+                target,
+                operator: _,
+                value,
+                postfix: _,
+            } => {
+                self.resolve_expr(value)?;
+                match target.as_ref() {
+                    // Updating a variable only reads it to write it back
+                    Expr::Variable { id, name } => self.resolve_local(name, *id, false),
+                    target => self.resolve_expr(target),
+                }
+            }
+            Expr::Object { id: _, entries } => {
+                for (_, value) in entries {
+                    self.resolve_expr(value)?;
+                }
+
+                Ok(())
+            }
             Expr::Logical {
                 id: _,
                 left,
@@ -296,23 +594,35 @@ impl Resolver {
                 self.resolve_expr(object)
             }
             Expr::This { id: _, keyword } => {
-                if self.current_function != FunctionType::Method {
-                    return Err("Cannot use 'this' keyword outside of a class".to_string());
+                if self.current_class == ClassType::None {
+                    return Err(MossError::resolve(
+                        "Cannot use 'this' keyword outside of a class",
+                        keyword,
+                    ));
                 }
-                self.resolve_local(keyword, expr.get_id())
+                self.resolve_local(keyword, expr.get_id(), true)
             }
             Expr::Super {
                 id: _,
                 keyword,
                 method: _,
             } => {
-                if self.current_function != FunctionType::Method {
-                    return Err("Cannot use 'super' keyword outside of a class".to_string());
+                match self.current_class {
+                    ClassType::None => {
+                        return Err(MossError::resolve(
+                            "Cannot use 'super' keyword outside of a class",
+                            keyword,
+                        ))
+                    }
+                    ClassType::Class => {
+                        return Err(MossError::resolve("Class has no superclass", keyword))
+                    }
+                    ClassType::Subclass => (),
                 }
-                if self.scopes.len() < 3 || !self.scopes[self.scopes.len() - 3].contains_key("super") {
-                    return Err("Class has no superclass".to_string());
+                if let Some(this) = self.lookup("this", true) {
+                    self.locals.receivers.insert(expr.get_id(), this);
                 }
-                self.resolve_local(keyword, expr.get_id())
+                self.resolve_local(keyword, expr.get_id(), true)
             }
             Expr::Unary {
                 id: _,
@@ -321,10 +631,11 @@ impl Resolver {
             } => self.resolve_expr(right),
             Expr::AnonFunction {
                 id: _,
-                paren: _,
+                paren,
                 arguments,
                 body,
             } => self.resolve_function_helper(
+                paren,
                 arguments,
                 &body.iter().map(|b| b.as_ref()).collect(),
                 FunctionType::Function,
@@ -332,16 +643,23 @@ impl Resolver {
         }
     }
 
-    fn resolve_expr_var(&mut self, expr: &Expr, resolve_id: usize) -> Result<(), String> {
+    fn resolve_expr_var(&mut self, expr: &Expr, resolve_id: usize) -> Result<(), MossError> {
         match expr {
             Expr::Variable { id: _, name } => {
                 if !self.scopes.is_empty() {
-                    if let Some(false) = self.scopes[self.scopes.len() - 1].get(&name.lexeme) {
-                        return Err("Can't read local variable in its own initializer".to_string());
+                    let local = self.scopes[self.scopes.len() - 1].get(&name.lexeme);
+                    if local.is_some_and(|local| !local.defined) {
+                        self.warn(
+                            format!(
+                                "'{}' in its own initializer refers to an outer variable",
+                                name.lexeme
+                            ),
+                            name,
+                        );
                     }
                 }
 
-                self.resolve_local(name, resolve_id)
+                self.resolve_local(name, resolve_id, true)
             }
             Expr::Call {
                 id: _,
@@ -349,35 +667,82 @@ impl Resolver {
                 paren: _,
                 arguments: _,
             } => match callee.as_ref() {
-                Expr::Variable { id: _, name } => self.resolve_local(&name, resolve_id),
+                Expr::Variable { id: _, name } => self.resolve_local(name, resolve_id, true),
                 _ => panic!("Wrong type in resolve_expr_var"),
             },
             _ => panic!("Wrong type in resolve_expr_var"),
         }
     }
 
-    fn resolve_local(&mut self, name: &Token, resolve_id: usize) -> Result<(), String> {
+    // `read` is false when the variable is only written, which doesn't count
+    // as using it
+    fn resolve_local(
+        &mut self,
+        name: &Token,
+        resolve_id: usize,
+        read: bool,
+    ) -> Result<(), MossError> {
+        // Anything not found is assumed to be global
+        if let Some(slot) = self.lookup(&name.lexeme, read) {
+            self.locals.variables.insert(resolve_id, slot);
+        }
+        Ok(())
+    }
+
+    // Where the innermost local called `name` is, capturing it into every
+    // function between it and the code being resolved. A local is only in
+    // scope once it is defined, so its initializer sees the outer one.
+    fn lookup(&mut self, name: &str, read: bool) -> Option<Slot> {
         let size = self.scopes.len();
-        if size == 0 {
-            return Ok(());
+        let scope = (0..size)
+            .rev()
+            .find(|&i| self.scopes[i].get(name).is_some_and(|local| local.defined))?;
+        let local = self.scopes[scope].get_mut(name)?;
+        local.used |= read;
+        let slot = local.slot;
+
+        let base = self.functions.last().map_or(0, |function| function.base);
+        if scope >= base {
+            Some(Slot::Local {
+                depth: size - 1 - scope,
+                slot,
+            })
+        } else {
+            Some(Slot::Upvalue(self.capture(self.functions.len() - 1, scope, slot)))
         }
+    }
 
-        for i in (0..=(size - 1)).rev() {
-            let scope = &self.scopes[i];
-            if scope.contains_key(&name.lexeme) {
-                self.locals.insert(resolve_id, size - 1 - i);
-                return Ok(());
+    // The index among the captures of `function` of the variable in `slot` of
+    // the scope at index `scope`, which is outside of the function
+    fn capture(&mut self, function: usize, scope: usize, slot: usize) -> usize {
+        let base = self.functions[function].base;
+        let enclosing_base = match function {
+            0 => 0,
+            _ => self.functions[function - 1].base,
+        };
+        let capture = if scope >= enclosing_base {
+            Capture::Local {
+                depth: base - 1 - scope,
+                slot,
+            }
+        } else {
+            Capture::Upvalue(self.capture(function - 1, scope, slot))
+        };
+
+        let captures = &mut self.functions[function].captures;
+        match captures.iter().position(|c| *c == capture) {
+            Some(index) => index,
+            None => {
+                captures.push(capture);
+                captures.len() - 1
             }
         }
-
-        // Assume it's global
-        Ok(())
     }
 
-    fn resolve_expr_assign(&mut self, expr: &Expr, resolve_id: usize) -> Result<(), String> {
+    fn resolve_expr_assign(&mut self, expr: &Expr, resolve_id: usize) -> Result<(), MossError> {
         if let Expr::Assign { id: _, name, value } = expr {
             self.resolve_expr(value.as_ref())?;
-            self.resolve_local(name, resolve_id)?;
+            self.resolve_local(name, resolve_id, false)?;
         } else {
             panic!("Wrong type in resolve assign");
         }
@@ -385,3 +750,156 @@ impl Resolver {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn warnings(source: &str) -> Vec<String> {
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        let (_, warnings) = Resolver::new()
+            .resolve_with_warnings(&stmts.iter().collect())
+            .unwrap();
+        warnings
+            .iter()
+            .map(|w| format!("{}: {}", w.span.unwrap().line, w.message))
+            .collect()
+    }
+
+    #[test]
+    fn unused_locals_and_parameters() {
+        let source = "var global = 1;\n\
+                      fun f(a, b, _c) {\n\
+                      var d = a;\n\
+                      fun g() {}\n\
+                      return d;\n\
+                      }";
+        assert_eq!(
+            warnings(source),
+            vec![
+                "2: Parameter 'b' is never used",
+                "4: Local function 'g' is never used"
+            ]
+        );
+    }
+
+    #[test]
+    fn methods_and_this_are_not_reported() {
+        let source = "class A { init() { this.x = 1; } area() { return 0; } }";
+        assert!(warnings(source).is_empty());
+    }
+
+    #[test]
+    fn shadowing_an_outer_local() {
+        let source = "fun f(x) {\n\
+                      { var x = 2; print x; }\n\
+                      for (var i = 0; i < x; i++) { for (var i = 0; i < 2; i++) print i; }\n\
+                      }";
+        assert_eq!(
+            warnings(source),
+            vec![
+                "2: 'x' shadows the variable declared on line 1",
+                "3: 'i' shadows the variable declared on line 3"
+            ]
+        );
+    }
+
+    #[test]
+    fn shadowing_a_global() {
+        let source = "var a = 1;\n\
+                      fun f(a) { return a; }\n\
+                      fun g() { var f = 2; return f; }\n\
+                      fun h(later) { return later; }\n\
+                      var later = 3;";
+        assert_eq!(
+            warnings(source),
+            vec![
+                "2: 'a' shadows the variable declared on line 1",
+                "3: 'f' shadows the variable declared on line 2"
+            ]
+        );
+    }
+
+    #[test]
+    fn locals_that_are_only_written_are_unused() {
+        let source = "fun f() {\n\
+                      var x = 1;\n\
+                      x = 2;\n\
+                      var y = 0;\n\
+                      y++;\n\
+                      y += 2;\n\
+                      var z = 0;\n\
+                      z += 1;\n\
+                      return z;\n\
+                      }";
+        assert_eq!(
+            warnings(source),
+            vec![
+                "2: Local variable 'x' is never used",
+                "4: Local variable 'y' is never used"
+            ]
+        );
+    }
+
+    #[test]
+    fn code_after_a_jump_is_unreachable() {
+        let source = "while (true) {\n\
+                      break;\n\
+                      print 1;\n\
+                      print 2;\n\
+                      }\n\
+                      fun f() { return; }";
+        assert_eq!(
+            warnings(source),
+            vec!["2: Code after 'break' is unreachable"]
+        );
+    }
+
+    #[test]
+    fn own_initializer_reads_and_top_level_returns() {
+        let source = "var a = 1;\n\
+                      { var a = a + 1; print a; }\n\
+                      return;";
+        assert_eq!(
+            warnings(source),
+            vec![
+                "2: 'a' shadows the variable declared on line 1",
+                "2: 'a' in its own initializer refers to an outer variable",
+                "3: 'return' outside of a function ends the program"
+            ]
+        );
+    }
+
+    #[test]
+    fn functions_capture_only_free_variables() {
+        let source = "fun outer(a, b) {\n\
+                      var c = 1;\n\
+                      fun middle() {\n\
+                      fun inner() { return c + a; }\n\
+                      return inner;\n\
+                      }\n\
+                      return b + middle;\n\
+                      }";
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        let locals = Resolver::new().resolve(&stmts.iter().collect()).unwrap();
+
+        let captures = |name: &str| locals.captures[&source.find(name).unwrap()].clone();
+        assert!(captures("outer").is_empty());
+        // `middle` is defined in the scope of `outer`, where `c` is slot 2
+        assert_eq!(
+            captures("middle"),
+            vec![
+                Capture::Local { depth: 0, slot: 2 },
+                Capture::Local { depth: 0, slot: 0 }
+            ]
+        );
+        assert_eq!(
+            captures("inner"),
+            vec![Capture::Upvalue(0), Capture::Upvalue(1)]
+        );
+    }
+}
@@ -1,13 +1,13 @@
+use crate::error::{MossError, Span};
 use std::collections::HashMap;
 use std::string::String;
 
 fn is_digit(ch: char) -> bool {
-    ch as u8 >= '0' as u8 && ch as u8 <= '9' as u8
+    ch.is_ascii_digit()
 }
 
 fn is_alpha(ch: char) -> bool {
-    let uch = ch as u8;
-    (uch >= 'a' as u8 && uch <= 'z' as u8) || (uch >= 'A' as u8 && uch <= 'Z' as u8) || (ch == '_')
+    ch.is_ascii_alphabetic() || ch == '_'
 }
 
 fn is_alpha_numeric(ch: char) -> bool {
@@ -17,12 +17,16 @@ fn is_alpha_numeric(ch: char) -> bool {
 fn get_keywords_hashmap() -> HashMap<&'static str, TokenType> {
     HashMap::from([
         ("and", And),
+        ("break", Break),
         ("class", Class),
+        ("continue", Continue),
         ("else", Else),
+        ("export", Export),
         ("false", False),
         ("for", For),
         ("fun", Fun),
         ("if", If),
+        ("import", Import),
         ("nil", Nil),
         ("or", Or),
         ("print", Print),
@@ -38,9 +42,14 @@ fn get_keywords_hashmap() -> HashMap<&'static str, TokenType> {
 pub struct Scanner {
     source: String,
     tokens: Vec<Token>,
+    // Byte offsets of the token being scanned and of the next character
     start: usize,
     current: usize,
     line: usize,
+    // The column of the next character, and where the token being scanned starts
+    column: usize,
+    start_line: usize,
+    start_column: usize,
 
     keywords: HashMap<&'static str, TokenType>,
 }
@@ -53,17 +62,22 @@ impl Scanner {
             start: 0,
             current: 0,
             line: 1,
+            column: 1,
+            start_line: 1,
+            start_column: 1,
             keywords: get_keywords_hashmap(),
         }
     }
 
-    pub fn scan_tokens(self: &mut Self) -> Result<Vec<Token>, String> {
+    pub fn scan_tokens(&mut self) -> Result<Vec<Token>, Vec<MossError>> {
         let mut errors = vec![];
         while !self.is_at_end() {
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = self.column;
             match self.scan_token() {
                 Ok(_) => (),
-                Err(msg) => errors.push(msg),
+                Err(msg) => errors.push(MossError::scan(msg, self.span())),
             }
         }
 
@@ -72,27 +86,34 @@ impl Scanner {
             lexeme: "".to_string(),
             literal: None,
             line_number: self.line,
+            offset: self.current,
+            column: self.column,
         });
 
-        if errors.len() > 0 {
-            let mut joined = "".to_string();
-            for error in errors {
-                joined.push_str(&error);
-                joined.push_str("\n");
-            }
-            return Err(joined);
+        if !errors.is_empty() {
+            return Err(errors);
         }
 
         Ok(self.tokens.clone())
     }
 
+    // The span of the token being scanned
+    fn span(&self) -> Span {
+        Span {
+            offset: self.start,
+            len: self.source[self.start..self.current].chars().count(),
+            line: self.start_line,
+            column: self.start_column,
+        }
+    }
+
     // var test = 0.01;
 
-    fn is_at_end(self: &Self) -> bool {
+    fn is_at_end(&self) -> bool {
         self.current >= self.source.len()
     }
 
-    fn scan_token(self: &mut Self) -> Result<(), String> {
+    fn scan_token(&mut self) -> Result<(), String> {
         let c = self.advance();
 
         match c {
@@ -100,12 +121,43 @@ impl Scanner {
             ')' => self.add_token(RightParen),
             '{' => self.add_token(LeftBrace),
             '}' => self.add_token(RightBrace),
+            '[' => self.add_token(LeftBracket),
+            ']' => self.add_token(RightBracket),
             ',' => self.add_token(Comma),
             '.' => self.add_token(Dot),
-            '-' => self.add_token(Minus),
-            '+' => self.add_token(Plus),
+            ':' => self.add_token(Colon),
+            '-' => {
+                let token = if self.char_match('-') {
+                    MinusMinus
+                } else if self.char_match('=') {
+                    MinusEqual
+                } else {
+                    Minus
+                };
+
+                self.add_token(token);
+            }
+            '+' => {
+                let token = if self.char_match('+') {
+                    PlusPlus
+                } else if self.char_match('=') {
+                    PlusEqual
+                } else {
+                    Plus
+                };
+
+                self.add_token(token);
+            }
             ';' => self.add_token(Semicolon),
-            '*' => self.add_token(Star),
+            '*' => {
+                let token = if self.char_match('=') {
+                    StarEqual
+                } else {
+                    Star
+                };
+
+                self.add_token(token);
+            }
             '!' => {
                 let token = if self.char_match('=') {
                     // !=
@@ -152,19 +204,28 @@ impl Scanner {
                         }
                         self.advance();
                     }
+                } else if self.char_match('=') {
+                    self.add_token(SlashEqual);
                 } else {
                     self.add_token(Slash);
                 }
             },
+            '#' => {
+                if self.char_match('{') {
+                    self.add_token(HashLeftBrace);
+                } else {
+                    return Err("Expected '{' after '#'".to_string());
+                }
+            }
             '|' => {
                 if self.char_match('>') {
                     self.add_token(Pipe);
                 } else {
-                    return Err(format!("Expected '>' at line {}", self.line));
+                    return Err("Expected '>' after '|'".to_string());
                 }
             },
             ' ' | '\r' | '\t' => {}
-            '\n' => self.line += 1,
+            '\n' => self.new_line(),
             '"' => self.string()?,
 
             c => {
@@ -173,7 +234,7 @@ impl Scanner {
                 } else if is_alpha(c) {
                     self.identifier();
                 } else {
-                    return Err(format!("Unrecognized char at line {}: {}", self.line, c));
+                    return Err(format!("Unrecognized char: {}", c));
                 }
             }
         }
@@ -194,7 +255,7 @@ impl Scanner {
         }
     }
 
-    fn number(self: &mut Self) -> Result<(), String> {
+    fn number(&mut self) -> Result<(), String> {
         while is_digit(self.peek()) {
             self.advance();
         }
@@ -216,20 +277,16 @@ impl Scanner {
         Ok(())
     }
 
-    fn peek_next(self: &Self) -> char {
-        if self.current + 1 >= self.source.len() {
-            return '\0';
-        }
-
-        self.source.chars().nth(self.current + 1).unwrap()
+    fn peek_next(&self) -> char {
+        self.source[self.current..].chars().nth(1).unwrap_or('\0')
     }
 
-    fn string(self: &mut Self) -> Result<(), String> {
+    fn string(&mut self) -> Result<(), String> {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            let c = self.advance();
+            if c == '\n' {
+                self.new_line();
             }
-            self.advance();
         }
 
         if self.is_at_end() {
@@ -245,44 +302,47 @@ impl Scanner {
         Ok(())
     }
 
-    fn peek(self: &Self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        }
-        self.source.chars().nth(self.current).unwrap()
+    fn peek(&self) -> char {
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
-    fn char_match(self: &mut Self, ch: char) -> bool {
-        if self.is_at_end() {
-            return false;
-        }
-        if self.source.chars().nth(self.current).unwrap() != ch {
-            return false;
+    fn char_match(&mut self, ch: char) -> bool {
+        if self.is_at_end() || self.peek() != ch {
+            false
         } else {
-            self.current += 1;
-            return true;
+            self.current += ch.len_utf8();
+            self.column += 1;
+            true
         }
     }
 
-    fn advance(self: &mut Self) -> char {
-        let c = self.source.chars().nth(self.current).unwrap();
-        self.current += 1;
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.column = 1;
+    }
+
+    fn advance(&mut self) -> char {
+        let c = self.peek();
+        self.current += c.len_utf8();
+        self.column += 1;
 
         c
     }
 
-    fn add_token(self: &mut Self, token_type: TokenType) {
+    fn add_token(&mut self, token_type: TokenType) {
         self.add_token_lit(token_type, None);
     }
 
-    fn add_token_lit(self: &mut Self, token_type: TokenType, literal: Option<LiteralValue>) {
+    fn add_token_lit(&mut self, token_type: TokenType, literal: Option<LiteralValue>) {
         let text = self.source[self.start..self.current].to_string();
 
         self.tokens.push(Token {
-            token_type: token_type,
+            token_type,
             lexeme: text,
-            literal: literal,
-            line_number: self.line,
+            literal,
+            line_number: self.start_line,
+            offset: self.start,
+            column: self.start_column,
         });
     }
 }
@@ -294,7 +354,10 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
@@ -311,8 +374,15 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
-    Pipe, // |>
-    Gets, // <-
+    MinusEqual,
+    MinusMinus,
+    PlusEqual,
+    PlusPlus,
+    SlashEqual,
+    StarEqual,
+    Pipe,          // |>
+    Gets,          // <-
+    HashLeftBrace, // #{
 
     // Literals
     Identifier,
@@ -321,12 +391,16 @@ pub enum TokenType {
 
     // Keywords
     And,
+    Break,
     Class,
+    Continue,
     Else,
+    Export,
     False,
     Fun,
     For,
     If,
+    Import,
     Nil,
     Or,
     Print,
@@ -360,10 +434,22 @@ pub struct Token {
     pub lexeme: String,
     pub literal: Option<LiteralValue>,
     pub line_number: usize,
+    pub offset: usize,
+    pub column: usize,
 }
 
 impl Token {
-    pub fn to_string(self: &Self) -> String {
+    pub fn span(&self) -> Span {
+        Span {
+            offset: self.offset,
+            len: self.lexeme.chars().count(),
+            line: self.line_number,
+            column: self.column,
+        }
+    }
+
+    #[allow(clippy::inherent_to_string)]
+    pub fn to_string(&self) -> String {
         format!("{} {} {:?}", self.token_type, self.lexeme, self.literal)
     }
 }
@@ -388,6 +474,139 @@ mod tests {
         assert_eq!(scanner.tokens[6].token_type, Eof);
     }
 
+    #[test]
+    fn handle_brackets() {
+        let source = "xs[0] = [1, 2];";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        let types: Vec<TokenType> = scanner.tokens.iter().map(|t| t.token_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                Identifier,
+                LeftBracket,
+                Number,
+                RightBracket,
+                Equal,
+                LeftBracket,
+                Number,
+                Comma,
+                Number,
+                RightBracket,
+                Semicolon,
+                Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn handle_compound_operators() {
+        let source = "i++ --j a += b -= c *= d /= e - f";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        let types: Vec<TokenType> = scanner.tokens.iter().map(|t| t.token_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                Identifier,
+                PlusPlus,
+                MinusMinus,
+                Identifier,
+                Identifier,
+                PlusEqual,
+                Identifier,
+                MinusEqual,
+                Identifier,
+                StarEqual,
+                Identifier,
+                SlashEqual,
+                Identifier,
+                Minus,
+                Identifier,
+                Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn handle_map_literal() {
+        let source = "#{a: 1, \"b\": 2}";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        let types: Vec<TokenType> = scanner.tokens.iter().map(|t| t.token_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                HashLeftBrace,
+                Identifier,
+                Colon,
+                Number,
+                Comma,
+                StringLit,
+                Colon,
+                Number,
+                RightBrace,
+                Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn handle_token_spans() {
+        let source = "var a = 1;\n  print \"two\nlines\" + a;";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        let print = &scanner.tokens[5];
+        assert_eq!((print.line_number, print.column, print.offset), (2, 3, 13));
+
+        let string = &scanner.tokens[6];
+        assert_eq!(string.line_number, 2);
+        assert_eq!(string.column, 9);
+
+        // Columns restart after the newline inside the string
+        let plus = &scanner.tokens[7];
+        assert_eq!((plus.line_number, plus.column, plus.offset), (3, 8, 31));
+    }
+
+    #[test]
+    fn handle_non_ascii_source() {
+        let source = "print \"héllo\";\nx + \"é\" // ünïcode\n\"ok\"";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        let hello = &scanner.tokens[1];
+        assert_eq!(hello.lexeme, "\"héllo\"");
+        assert_eq!(hello.span().len, 7);
+
+        // Offsets count bytes, columns count characters
+        let plus = &scanner.tokens[4];
+        assert_eq!((plus.line_number, plus.column, plus.offset), (2, 3, 18));
+        let e = &scanner.tokens[5];
+        assert_eq!(e.lexeme, "\"é\"");
+        let ok = &scanner.tokens[6];
+        assert_eq!((ok.line_number, ok.column), (3, 1));
+        assert_eq!(&source[ok.offset..], "\"ok\"");
+
+        let errors = Scanner::new("\"é\" ¤").scan_tokens().unwrap_err();
+        let span = errors[0].span.unwrap();
+        assert_eq!((span.column, span.offset, span.len), (5, 5, 1));
+    }
+
+    #[test]
+    fn unrecognized_char_has_location() {
+        let source = "var a = 1;\nprint @;";
+        let mut scanner = Scanner::new(source);
+        let errors = scanner.scan_tokens().unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        let span = errors[0].span.unwrap();
+        assert_eq!((span.line, span.column, span.offset, span.len), (2, 7, 17, 1));
+    }
+
     #[test]
     fn handle_two_char_tokens() {
         let source = "! != == >=";
@@ -0,0 +1,101 @@
+//! Functions on lists and maps.
+
+use super::{argument_error, index_arg, string_arg};
+use crate::environment::define_native;
+use crate::expr::{sorted_keys, LiteralValue};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub fn define(env: &mut HashMap<String, LiteralValue>) {
+    define_native(env, "keys", 1, keys_impl);
+    define_native(env, "has", 2, has_impl);
+    define_native(env, "push", 2, push_impl);
+    define_native(env, "pop", 1, pop_impl);
+    define_native(env, "insert", 3, insert_impl);
+    define_native(env, "remove", 2, remove_impl);
+}
+
+// The argument at `index` as a list
+fn list_arg<'a>(
+    name: &str,
+    args: &'a [LiteralValue],
+    index: usize,
+) -> Result<&'a Rc<RefCell<Vec<LiteralValue>>>, String> {
+    match &args[index] {
+        LiteralValue::List(elements) => Ok(elements),
+        other => Err(argument_error(name, index, "a List", other)),
+    }
+}
+
+// The keys of a map as a list of strings, in sorted order
+fn keys_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    match &args[0] {
+        LiteralValue::Map(entries) => {
+            let keys = sorted_keys(&entries.borrow())
+                .into_iter()
+                .map(LiteralValue::StringValue)
+                .collect();
+            Ok(LiteralValue::list(keys))
+        }
+        other => Err(argument_error("keys", 0, "a Map", other)),
+    }
+}
+
+// Whether a map has a key, which reading a missing one would be an error
+fn has_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    match &args[0] {
+        LiteralValue::Map(entries) => {
+            let key = string_arg("has", args, 1)?;
+            Ok(LiteralValue::from_bool(entries.borrow().contains_key(key)))
+        }
+        other => Err(argument_error("has", 0, "a Map", other)),
+    }
+}
+
+// Adds a value at the end of a list and returns the new length
+fn push_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let mut elements = list_arg("push", args, 0)?.borrow_mut();
+    elements.push(args[1].clone());
+    Ok(LiteralValue::Number(elements.len() as f64))
+}
+
+// Removes the last value of a list and returns it
+fn pop_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let mut elements = list_arg("pop", args, 0)?.borrow_mut();
+    elements
+        .pop()
+        .ok_or_else(|| "Cannot pop from an empty list".to_string())
+}
+
+// insert(list, i, value) puts `value` at position `i`, moving the ones after
+// it up. `i` can be the length of the list, which appends.
+fn insert_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let list = list_arg("insert", args, 0)?;
+    let index = index_arg("insert", args, 1)?;
+    let mut elements = list.borrow_mut();
+    if index > elements.len() {
+        return Err(format!(
+            "Index {} out of bounds for length {}",
+            index,
+            elements.len()
+        ));
+    }
+    elements.insert(index, args[2].clone());
+    Ok(LiteralValue::Nil)
+}
+
+// Removes the value at position `i` of a list and returns it
+fn remove_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let list = list_arg("remove", args, 0)?;
+    let index = index_arg("remove", args, 1)?;
+    let mut elements = list.borrow_mut();
+    if index >= elements.len() {
+        return Err(format!(
+            "Index {} out of bounds for length {}",
+            index,
+            elements.len()
+        ));
+    }
+    Ok(elements.remove(index))
+}
@@ -0,0 +1,155 @@
+//! Reading and writing files, and reading lines from stdin. Paths are
+//! relative to the working directory.
+
+use super::string_arg;
+use crate::environment::define_native;
+use crate::expr::LiteralValue;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+type Native = fn(&[LiteralValue]) -> Result<LiteralValue, String>;
+
+// The natives that touch the filesystem, with their arity
+const FILESYSTEM: [(&str, usize, Native); 5] = [
+    ("read_file", 1, read_file_impl),
+    ("read_lines", 1, read_lines_impl),
+    ("write_file", 2, write_file_impl),
+    ("append_file", 2, append_file_impl),
+    ("exists", 1, exists_impl),
+];
+
+pub fn define(env: &mut HashMap<String, LiteralValue>) {
+    for (name, arity, fun) in FILESYSTEM {
+        define_native(env, name, arity, fun);
+    }
+    define_native(env, "read_line", 0, read_line_impl);
+}
+
+/// Replaces the filesystem natives with ones that fail, for programs that
+/// must not touch files
+pub fn disable_filesystem(env: &mut HashMap<String, LiteralValue>) {
+    for (name, arity, _) in FILESYSTEM {
+        define_native(env, name, arity, disabled_impl);
+    }
+}
+
+fn disabled_impl(_args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    Err("Filesystem access is disabled".to_string())
+}
+
+fn io_error(action: &str, path: &str, error: io::Error) -> String {
+    format!("Could not {} file '{}': {}", action, path, error)
+}
+
+fn read_file_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let path = string_arg("read_file", args, 0)?;
+    let contents = fs::read_to_string(path).map_err(|e| io_error("read", path, e))?;
+    Ok(LiteralValue::StringValue(contents))
+}
+
+// The lines of a file without their line endings
+fn read_lines_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let path = string_arg("read_lines", args, 0)?;
+    let contents = fs::read_to_string(path).map_err(|e| io_error("read", path, e))?;
+    let lines = contents
+        .lines()
+        .map(|line| LiteralValue::StringValue(line.to_string()))
+        .collect();
+    Ok(LiteralValue::list(lines))
+}
+
+// Creates the file, or replaces what it contains
+fn write_file_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let path = string_arg("write_file", args, 0)?;
+    let contents = string_arg("write_file", args, 1)?;
+    fs::write(path, contents).map_err(|e| io_error("write", path, e))?;
+    Ok(LiteralValue::Nil)
+}
+
+fn append_file_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let path = string_arg("append_file", args, 0)?;
+    let contents = string_arg("append_file", args, 1)?;
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|e| io_error("append to", path, e))?;
+    Ok(LiteralValue::Nil)
+}
+
+fn exists_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let path = string_arg("exists", args, 0)?;
+    Ok(LiteralValue::from_bool(Path::new(path).exists()))
+}
+
+// The next line of stdin without its line ending, or nil at the end of input
+fn read_line_impl(_args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let mut line = String::new();
+    let read = io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .map_err(|e| format!("Could not read from stdin: {}", e))?;
+    if read == 0 {
+        return Ok(LiteralValue::Nil);
+    }
+
+    let end = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(end);
+    Ok(LiteralValue::StringValue(line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> LiteralValue {
+        LiteralValue::StringValue(s.to_string())
+    }
+
+    #[test]
+    fn write_append_and_read_back() {
+        let path = std::env::temp_dir().join(format!("moss-io-{}.txt", std::process::id()));
+        let path = string(&path.display().to_string());
+
+        write_file_impl(&[path.clone(), string("one\n")]).unwrap();
+        append_file_impl(&[path.clone(), string("two\r\n")]).unwrap();
+
+        assert_eq!(
+            read_file_impl(std::slice::from_ref(&path)),
+            Ok(string("one\ntwo\r\n"))
+        );
+        assert_eq!(
+            read_lines_impl(std::slice::from_ref(&path)),
+            Ok(LiteralValue::list(vec![string("one"), string("two")]))
+        );
+        assert_eq!(
+            exists_impl(std::slice::from_ref(&path)),
+            Ok(LiteralValue::True)
+        );
+
+        if let LiteralValue::StringValue(path) = path {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn disabled_natives_fail() {
+        let mut env = HashMap::new();
+        define(&mut env);
+        disable_filesystem(&mut env);
+
+        match &env["read_file"] {
+            LiteralValue::Callable(crate::expr::CallableImpl::NativeFunction(native)) => {
+                assert_eq!(native.arity, 1);
+                assert_eq!(
+                    (native.fun)(&[string("readme.md")]),
+                    Err("Filesystem access is disabled".to_string())
+                );
+            }
+            _ => panic!("Expected read_file to be a native"),
+        }
+    }
+}
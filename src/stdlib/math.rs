@@ -0,0 +1,200 @@
+//! The `math` map: number functions, constants and a random number
+//! generator that can be seeded to make scripts reproducible.
+
+use super::{number_arg, string_arg};
+use crate::environment::define_native;
+use crate::expr::LiteralValue;
+use std::cell::Cell;
+use std::collections::HashMap;
+
+pub fn define(env: &mut HashMap<String, LiteralValue>) {
+    let mut math = HashMap::new();
+    math.insert("PI".to_string(), LiteralValue::Number(std::f64::consts::PI));
+    math.insert("E".to_string(), LiteralValue::Number(std::f64::consts::E));
+
+    define_native(&mut math, "sqrt", 1, sqrt_impl);
+    define_native(&mut math, "floor", 1, floor_impl);
+    define_native(&mut math, "ceil", 1, ceil_impl);
+    define_native(&mut math, "round", 1, round_impl);
+    define_native(&mut math, "abs", 1, abs_impl);
+    define_native(&mut math, "pow", 2, pow_impl);
+    define_native(&mut math, "min", 2, min_impl);
+    define_native(&mut math, "max", 2, max_impl);
+    define_native(&mut math, "sin", 1, sin_impl);
+    define_native(&mut math, "cos", 1, cos_impl);
+    define_native(&mut math, "tan", 1, tan_impl);
+    define_native(&mut math, "atan2", 2, atan2_impl);
+    define_native(&mut math, "log", 1, log_impl);
+    define_native(&mut math, "random", 0, random_impl);
+    define_native(&mut math, "random_int", 2, random_int_impl);
+    define_native(&mut math, "seed", 1, seed_impl);
+
+    env.insert("math".to_string(), LiteralValue::map(math));
+}
+
+// Applies `f` to the only argument of the native `name`
+fn unary(name: &str, args: &[LiteralValue], f: fn(f64) -> f64) -> Result<LiteralValue, String> {
+    Ok(LiteralValue::Number(f(number_arg(name, args, 0)?)))
+}
+
+fn binary(
+    name: &str,
+    args: &[LiteralValue],
+    f: fn(f64, f64) -> f64,
+) -> Result<LiteralValue, String> {
+    let x = number_arg(name, args, 0)?;
+    let y = number_arg(name, args, 1)?;
+    Ok(LiteralValue::Number(f(x, y)))
+}
+
+fn sqrt_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    unary("sqrt", args, f64::sqrt)
+}
+
+fn floor_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    unary("floor", args, f64::floor)
+}
+
+fn ceil_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    unary("ceil", args, f64::ceil)
+}
+
+// Halfway cases round away from zero
+fn round_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    unary("round", args, f64::round)
+}
+
+fn abs_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    unary("abs", args, f64::abs)
+}
+
+fn pow_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    binary("pow", args, f64::powf)
+}
+
+fn min_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    binary("min", args, f64::min)
+}
+
+fn max_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    binary("max", args, f64::max)
+}
+
+fn sin_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    unary("sin", args, f64::sin)
+}
+
+fn cos_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    unary("cos", args, f64::cos)
+}
+
+fn tan_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    unary("tan", args, f64::tan)
+}
+
+// atan2(y, x) is the angle of the point (x, y)
+fn atan2_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    binary("atan2", args, f64::atan2)
+}
+
+// The natural logarithm
+fn log_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    unary("log", args, f64::ln)
+}
+
+thread_local! {
+    // Every program on a thread draws from the same generator, so that seeding
+    // it also covers the random numbers drawn in imported modules
+    static RNG_STATE: Cell<u64> = Cell::new(time_seed());
+}
+
+fn time_seed() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+// splitmix64, which is small and good enough for scripts. It is not suitable
+// for anything security related.
+fn next_random() -> u64 {
+    RNG_STATE.with(|state| {
+        let next = state.get().wrapping_add(0x9e3779b97f4a7c15);
+        state.set(next);
+
+        let mut z = next;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    })
+}
+
+// A number in [0, 1)
+fn random_float() -> f64 {
+    (next_random() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn random_impl(_args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    Ok(LiteralValue::Number(random_float()))
+}
+
+// A whole number between `min` and `max`, both included
+fn random_int_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let min = number_arg("random_int", args, 0)?.ceil();
+    let max = number_arg("random_int", args, 1)?.floor();
+    if min > max {
+        return Err(format!(
+            "There is no whole number between {} and {}",
+            args[0].to_string(),
+            args[1].to_string()
+        ));
+    }
+
+    let value = min + (random_float() * (max - min + 1.0)).floor();
+    Ok(LiteralValue::Number(value.min(max)))
+}
+
+// Seeding with a number or a string restarts the same sequence of random numbers
+fn seed_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let seed = match &args[0] {
+        LiteralValue::Number(x) => x.to_bits(),
+        _ => string_arg("seed", args, 0)?
+            .bytes()
+            .fold(0xcbf29ce484222325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            }),
+    };
+    RNG_STATE.with(|state| state.set(seed));
+    Ok(LiteralValue::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(count: usize) -> Vec<LiteralValue> {
+        (0..count).map(|_| random_impl(&[]).unwrap()).collect()
+    }
+
+    #[test]
+    fn seeding_repeats_the_sequence() {
+        seed_impl(&[LiteralValue::Number(42.0)]).unwrap();
+        let first = draw(5);
+        seed_impl(&[LiteralValue::Number(42.0)]).unwrap();
+        assert_eq!(draw(5), first);
+
+        seed_impl(&[LiteralValue::Number(7.0)]).unwrap();
+        assert_ne!(draw(5), first);
+    }
+
+    #[test]
+    fn random_int_stays_in_range() {
+        seed_impl(&[LiteralValue::StringValue("dice".to_string())]).unwrap();
+        let args = vec![LiteralValue::Number(1.0), LiteralValue::Number(6.0)];
+        for _ in 0..1000 {
+            match random_int_impl(&args).unwrap() {
+                LiteralValue::Number(x) => assert!((1.0..=6.0).contains(&x) && x.fract() == 0.0),
+                other => panic!("Expected a number, got {}", other.to_string()),
+            }
+        }
+    }
+}
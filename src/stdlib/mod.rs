@@ -0,0 +1,54 @@
+//! Native functions registered in the globals of every program, grouped by
+//! what they work on. Natives check their own arguments and return an error
+//! message instead of panicking on a bad one.
+
+pub mod collections;
+pub mod io;
+pub mod math;
+pub mod string;
+
+use crate::expr::LiteralValue;
+
+/// The argument at `index` as a string
+pub fn string_arg<'a>(
+    name: &str,
+    args: &'a [LiteralValue],
+    index: usize,
+) -> Result<&'a str, String> {
+    match &args[index] {
+        LiteralValue::StringValue(s) => Ok(s),
+        other => Err(argument_error(name, index, "a String", other)),
+    }
+}
+
+/// The argument at `index` as a number
+pub fn number_arg(name: &str, args: &[LiteralValue], index: usize) -> Result<f64, String> {
+    match &args[index] {
+        LiteralValue::Number(x) => Ok(*x),
+        other => Err(argument_error(name, index, "a Number", other)),
+    }
+}
+
+/// The argument at `index` as a position or count, which must be a
+/// non-negative whole number
+pub fn index_arg(name: &str, args: &[LiteralValue], index: usize) -> Result<usize, String> {
+    match &args[index] {
+        LiteralValue::Number(x) if *x >= 0.0 && x.fract() == 0.0 => Ok(*x as usize),
+        other => Err(argument_error(
+            name,
+            index,
+            "a non-negative whole number",
+            other,
+        )),
+    }
+}
+
+pub fn argument_error(name: &str, index: usize, expected: &str, got: &LiteralValue) -> String {
+    format!(
+        "Expected {} as argument {} of {} but got {}",
+        expected,
+        index + 1,
+        name,
+        got.to_string()
+    )
+}
@@ -0,0 +1,217 @@
+//! String functions. Positions and lengths count characters, not bytes.
+
+use super::{argument_error, index_arg, number_arg, string_arg};
+use crate::environment::define_native;
+use crate::expr::LiteralValue;
+use std::collections::HashMap;
+
+pub fn define(env: &mut HashMap<String, LiteralValue>) {
+    define_native(env, "len", 1, len_impl);
+    define_native(env, "substring", 3, substring_impl);
+    define_native(env, "index_of", 2, index_of_impl);
+    define_native(env, "split", 2, split_impl);
+    define_native(env, "join", 2, join_impl);
+    define_native(env, "trim", 1, trim_impl);
+    define_native(env, "upper", 1, upper_impl);
+    define_native(env, "lower", 1, lower_impl);
+    define_native(env, "replace", 3, replace_impl);
+    define_native(env, "starts_with", 2, starts_with_impl);
+    define_native(env, "char_code", 2, char_code_impl);
+    define_native(env, "from_char_code", 1, from_char_code_impl);
+}
+
+fn string(s: impl Into<String>) -> LiteralValue {
+    LiteralValue::StringValue(s.into())
+}
+
+// The length of a string, list or map
+fn len_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let len = match &args[0] {
+        LiteralValue::StringValue(s) => s.chars().count(),
+        LiteralValue::List(elements) => elements.borrow().len(),
+        LiteralValue::Map(entries) => entries.borrow().len(),
+        other => return Err(argument_error("len", 0, "a String, List or Map", other)),
+    };
+    Ok(LiteralValue::Number(len as f64))
+}
+
+// substring(s, start, end) is the characters from `start` up to, not including, `end`
+fn substring_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let s = string_arg("substring", args, 0)?;
+    let start = index_arg("substring", args, 1)?;
+    let end = index_arg("substring", args, 2)?;
+
+    let len = s.chars().count();
+    if start > end || end > len {
+        return Err(format!(
+            "Substring {}..{} is out of bounds for a string of length {}",
+            start, end, len
+        ));
+    }
+
+    Ok(string(
+        s.chars().skip(start).take(end - start).collect::<String>(),
+    ))
+}
+
+// The position of the first occurrence of `needle`, or -1
+fn index_of_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let s = string_arg("index_of", args, 0)?;
+    let needle = string_arg("index_of", args, 1)?;
+
+    let index = match s.find(needle) {
+        Some(byte) => s[..byte].chars().count() as f64,
+        None => -1.0,
+    };
+    Ok(LiteralValue::Number(index))
+}
+
+// Splitting on "" gives the characters of the string
+fn split_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let s = string_arg("split", args, 0)?;
+    let separator = string_arg("split", args, 1)?;
+
+    let parts = if separator.is_empty() {
+        s.chars().map(string).collect()
+    } else {
+        s.split(separator).map(string).collect()
+    };
+    Ok(LiteralValue::list(parts))
+}
+
+fn join_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let elements = match &args[0] {
+        LiteralValue::List(elements) => elements.borrow(),
+        other => return Err(argument_error("join", 0, "a List", other)),
+    };
+    let separator = string_arg("join", args, 1)?;
+
+    let mut parts = vec![];
+    for element in elements.iter() {
+        match element {
+            LiteralValue::StringValue(s) => parts.push(s.as_str()),
+            other => {
+                return Err(format!(
+                    "join can only join Strings but the list contains {}",
+                    other.to_string()
+                ))
+            }
+        }
+    }
+    Ok(string(parts.join(separator)))
+}
+
+fn trim_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    Ok(string(string_arg("trim", args, 0)?.trim()))
+}
+
+fn upper_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    Ok(string(string_arg("upper", args, 0)?.to_uppercase()))
+}
+
+fn lower_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    Ok(string(string_arg("lower", args, 0)?.to_lowercase()))
+}
+
+// Replaces every occurrence of `from`
+fn replace_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let s = string_arg("replace", args, 0)?;
+    let from = string_arg("replace", args, 1)?;
+    let to = string_arg("replace", args, 2)?;
+
+    if from.is_empty() {
+        return Err("replace cannot replace an empty string".to_string());
+    }
+    Ok(string(s.replace(from, to)))
+}
+
+fn starts_with_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let s = string_arg("starts_with", args, 0)?;
+    let prefix = string_arg("starts_with", args, 1)?;
+    Ok(LiteralValue::from_bool(s.starts_with(prefix)))
+}
+
+// The unicode code point of the character at `index`
+fn char_code_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let s = string_arg("char_code", args, 0)?;
+    let index = index_arg("char_code", args, 1)?;
+
+    match s.chars().nth(index) {
+        Some(c) => Ok(LiteralValue::Number(c as u32 as f64)),
+        None => Err(format!(
+            "Index {} is out of bounds for a string of length {}",
+            index,
+            s.chars().count()
+        )),
+    }
+}
+
+fn from_char_code_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let code = number_arg("from_char_code", args, 0)?;
+
+    let c = if code >= 0.0 && code.fract() == 0.0 && code <= u32::MAX as f64 {
+        char::from_u32(code as u32)
+    } else {
+        None
+    };
+    match c {
+        Some(c) => Ok(string(c)),
+        None => Err(format!("{} is not a valid character code", code)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(
+        fun: fn(&[LiteralValue]) -> Result<LiteralValue, String>,
+        args: Vec<LiteralValue>,
+    ) -> Result<LiteralValue, String> {
+        fun(&args)
+    }
+
+    #[test]
+    fn positions_count_characters() {
+        let s = string("héllo wörld");
+        assert_eq!(
+            call(index_of_impl, vec![s.clone(), string("w")]),
+            Ok(LiteralValue::Number(6.0))
+        );
+        assert_eq!(
+            call(
+                substring_impl,
+                vec![
+                    s.clone(),
+                    LiteralValue::Number(1.0),
+                    LiteralValue::Number(4.0)
+                ]
+            ),
+            Ok(string("éll"))
+        );
+        assert_eq!(
+            call(char_code_impl, vec![s, LiteralValue::Number(7.0)]),
+            Ok(LiteralValue::Number('ö' as u32 as f64))
+        );
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        assert_eq!(
+            call(upper_impl, vec![LiteralValue::Number(1.0)]),
+            Err("Expected a String as argument 1 of upper but got 1".to_string())
+        );
+        assert_eq!(
+            call(
+                substring_impl,
+                vec![
+                    string("abc"),
+                    LiteralValue::Number(2.0),
+                    LiteralValue::Number(5.0)
+                ]
+            ),
+            Err("Substring 2..5 is out of bounds for a string of length 3".to_string())
+        );
+        assert!(call(from_char_code_impl, vec![LiteralValue::Number(-1.0)]).is_err());
+    }
+}
@@ -2,6 +2,7 @@ use crate::expr::Expr;
 use crate::scanner::Token;
 
 #[derive(Debug, Clone)]
+#[allow(clippy::vec_box, clippy::enum_variant_names)]
 pub enum Stmt {
     Expression {
         expression: Expr,
@@ -29,24 +30,44 @@ pub enum Stmt {
     WhileStmt {
         condition: Expr,
         body: Box<Stmt>,
+        // The increment clause of a desugared `for`, run after the body and on `continue`
+        increment: Option<Expr>,
+    },
+    Break {
+        keyword: Token,
+    },
+    Continue {
+        keyword: Token,
     },
     Function {
         name: Token,
         params: Vec<Token>,
         body: Vec<Box<Stmt>>,
     },
+    // `fun name(params) <- "command";` runs the command with the arguments appended
     CmdFunction {
         name: Token,
-        cmd: String,
+        params: Vec<Token>,
+        argv: Vec<String>,
     },
     ReturnStmt {
         keyword: Token,
         value: Option<Expr>,
     },
+    // `import name from "path";` binds the exports of a module to `name`
+    Import {
+        keyword: Token,
+        name: Token,
+        path: String,
+    },
+    Export {
+        name: Token,
+    },
 }
 // 改了文件批处理测试方法， to_string out !
 impl Stmt {
     #[allow(dead_code)]
+    #[allow(clippy::inherent_to_string)]
     pub fn to_string(&self) -> String {
         use Stmt::*;
         match self {
@@ -59,27 +80,87 @@ impl Stmt {
             Block { statements } => format!(
                 "(block {})",
                 statements
-                    .into_iter()
+                    .iter()
                     .map(|stmt| stmt.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
+            Class {
+                name,
+                methods,
+                superclass,
+            } => format!(
+                "(class {}{}{})",
+                name.lexeme,
+                superclass
+                    .as_ref()
+                    .map(|s| format!(" < {}", s.to_string()))
+                    .unwrap_or_default(),
+                methods
+                    .iter()
+                    .map(|method| format!(" {}", method.to_string()))
                     .collect::<String>()
             ),
             IfStmt {
-                predicate: _,
-                then: _,
-                els: _,
-            } => todo!(),
+                predicate,
+                then,
+                els,
+            } => match els {
+                Some(els) => format!(
+                    "(if {} {} {})",
+                    predicate.to_string(),
+                    then.to_string(),
+                    els.to_string()
+                ),
+                None => format!("(if {} {})", predicate.to_string(), then.to_string()),
+            },
             WhileStmt {
-                condition: _,
-                body: _,
-            } => todo!(),
-            Function {
-                name: _,
-                params: _,
-                body: _,
-            } => todo!(),
-            CmdFunction { name: _, cmd: _ } => todo!(),
-            ReturnStmt { keyword: _, value: _ } => todo!(),
-            _ => todo!(),
+                condition,
+                body,
+                increment,
+            } => match increment {
+                Some(increment) => format!(
+                    "(while {} {} {})",
+                    condition.to_string(),
+                    body.to_string(),
+                    increment.to_string()
+                ),
+                None => format!("(while {} {})", condition.to_string(), body.to_string()),
+            },
+            Break { keyword: _ } => "(break)".to_string(),
+            Continue { keyword: _ } => "(continue)".to_string(),
+            Function { name, params, body } => format!(
+                "(fun {} ({}){})",
+                name.lexeme,
+                params
+                    .iter()
+                    .map(|p| p.lexeme.clone())
+                    .collect::<Vec<String>>()
+                    .join(" "),
+                body.iter()
+                    .map(|stmt| format!(" {}", stmt.to_string()))
+                    .collect::<String>()
+            ),
+            CmdFunction { name, params, argv } => format!(
+                "(cmd {} ({}) {:?})",
+                name.lexeme,
+                params
+                    .iter()
+                    .map(|p| p.lexeme.clone())
+                    .collect::<Vec<String>>()
+                    .join(" "),
+                argv
+            ),
+            ReturnStmt { keyword: _, value } => match value {
+                Some(value) => format!("(return {})", value.to_string()),
+                None => "(return)".to_string(),
+            },
+            Import {
+                keyword: _,
+                name,
+                path,
+            } => format!("(import {} {:?})", name.lexeme, path),
+            Export { name } => format!("(export {})", name.lexeme),
         }
     }
 }
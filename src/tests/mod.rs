@@ -1,112 +1,204 @@
+//! Runs the programs in `src/tests/cases` on both backends and compares what
+//! they print and the errors they raise with what the case expects.
+//!
+//! A case states its expectations with annotations on the lines that cause
+//! them:
+//!
+//! ```text
+//! print 1 + 2; // expect: 3
+//! print nil + 1; // expect runtime error: Plus is not implemented for operands nil and 1
+//! var = 1; // expect compile error: Expected variable name
+//! ```
+//!
+//! An error annotation also checks the line the error points at, so errors
+//! raised in another file (an imported module) can only be checked by the
+//! older format: the program after `// --- Test` and its expected stdout,
+//! commented out, after `// --- Expected`. Errors show there as `ERROR:`
+//! followed by their messages, like `moss e` prints them.
+//!
+//! A case runs as the file it is in, so it imports modules relative to
+//! itself, like `import geometry from "../modules/geometry.moss";`.
+//!
+//! `MOSS_CASE=strings cargo test execute_tests` only runs the cases whose
+//! name contains `strings`.
+
 #[cfg(test)]
 #[allow(clippy::module_inception)]
 mod tests {
-    use std::fs::{read_dir, read_to_string, DirEntry};
-    use std::process::Command;
+    use crate::{Backend, Buffer, ErrorKind, Moss, MossError, Output};
+    use std::fs::{read_dir, read_to_string};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::path::Path;
 
     #[test]
     fn execute_tests() {
-        let cases = read_dir("./src/tests/cases").unwrap();
+        execute_cases(Backend::TreeWalker);
+    }
 
-        let mut errors = vec![];
-        let mut msgs = vec![];
-        for case in cases {
-            let case = case.unwrap();
-            let name = case.path().display().to_string();
-            if name.contains("~") {
-                continue;
-            }
+    #[test]
+    fn execute_tests_vm() {
+        execute_cases(Backend::Vm);
+    }
 
-            match run_test(case) {
-                Ok(_) => {
-                    msgs.push(format!("Running {name:.<85}...ok"));
-                },
+    fn execute_cases(backend: Backend) {
+        let filter = std::env::var("MOSS_CASE").unwrap_or_default();
+        let mut paths: Vec<_> = read_dir("./src/tests/cases")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "moss"))
+            .filter(|path| path.display().to_string().contains(&filter))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty(), "No test case matches '{}'", filter);
+
+        let mut errors = vec![];
+        for path in &paths {
+            let name = path.display().to_string();
+            match run_case(path, backend) {
+                Ok(()) => println!("Running {name:.<85}...ok"),
                 Err(msg) => {
-                    errors.push(msg);
-                    msgs.push(format!("Running {name:.<85}...failed"));
+                    println!("Running {name:.<85}...failed");
+                    errors.push(format!("{}:\n{}", name, msg));
                 }
             }
         }
+        println!("Ran {} tests", paths.len());
 
-        println!("Ran {} tests", msgs.len());
-        msgs.sort();
-        for msg in msgs {
-            println!("{}", msg);
+        if !errors.is_empty() {
+            panic!("Errors:\n\n{}", errors.join("\n\n"));
         }
+    }
 
-        if errors.len() > 0 {
-            panic!("Errors:\n\n{}", errors.join("\n\n"));
+    fn run_case(path: &Path, backend: Backend) -> Result<(), String> {
+        let contents = read_to_string(path).unwrap();
+        let file = path.display().to_string();
+        let case = match contents.split_once("// --- Expected") {
+            Some((source, expected)) => Case::legacy(&file, source, expected),
+            None => Case::annotated(&file, &contents),
+        };
+
+        let buffer = Buffer::default();
+        let mut moss = Moss::new(backend)
+            .in_file(&file)
+            .with_output(Output::new(buffer.clone()));
+        let result = catch_unwind(AssertUnwindSafe(|| moss.eval(&case.source)))
+            .map_err(|_| "The interpreter panicked".to_string())?;
+
+        let mut actual = normalize(buffer.contents().lines());
+        if let Err(errors) = result {
+            actual.extend(normalize(case.errors(&errors)));
+        }
+
+        if actual == case.expected {
+            Ok(())
+        } else {
+            Err(diff(&case.expected, &actual))
         }
     }
 
-    fn run_test(file: DirEntry) -> Result<(), String> {
-        // Parse input and expected
-        let contents = read_to_string(file.path()).unwrap();
-        let lines = contents.split("\n").collect::<Vec<&str>>();
+    // Lines are compared without the whitespace around them. A program
+    // cannot print a blank line, so blank ones are dropped.
+    fn normalize<S: AsRef<str>>(lines: impl IntoIterator<Item = S>) -> Vec<String> {
+        lines
+            .into_iter()
+            .map(|line| line.as_ref().trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect()
+    }
 
-        let mut test_code = vec![];
+    struct Case {
+        file: String,
+        source: String,
+        expected: Vec<String>,
+        annotated: bool,
+    }
 
-        let mut idx = None;
-        for (i, line) in lines.iter().enumerate() {
-            if line.starts_with("// --- Test") {
-                continue;
+    impl Case {
+        // Every `// ` line after `// --- Expected` is a line of stdout
+        fn legacy(file: &str, source: &str, expected: &str) -> Case {
+            let source = source.replacen("// --- Test", "", 1);
+            let expected = normalize(
+                expected
+                    .lines()
+                    .map(|line| line.get(3..).unwrap_or_default()),
+            );
+            Case {
+                file: file.to_string(),
+                source,
+                expected,
+                annotated: false,
             }
-            if line.starts_with("// --- Expected") {
-                idx = Some(i);
-                break;
-            }
-            test_code.push(line.clone());
         }
 
-        let idx = idx.expect(&format!(
-            "{:#?}: No expected section in test case definition",
-            file.file_name()
-        ));
-
-        let mut expected_output = vec![];
+        fn annotated(file: &str, source: &str) -> Case {
+            let mut expected = vec![];
+            let mut errors = vec![];
+            for (i, line) in source.lines().enumerate() {
+                let line_number = i + 1;
+                if let Some((_, output)) = line.split_once("// expect: ") {
+                    expected.push(output.to_string());
+                } else if let Some((_, message)) = line.split_once("// expect runtime error: ") {
+                    errors.push(format!("[line {}] runtime error: {}", line_number, message));
+                } else if let Some((_, message)) = line.split_once("// expect compile error: ") {
+                    errors.push(format!("[line {}] compile error: {}", line_number, message));
+                }
+            }
+            expected.extend(errors);
 
-        for line in &lines[idx + 1..] {
-            if line.len() > 0 {
-                let string = line.to_string();
-                expected_output.push((string[3..]).to_string());
+            Case {
+                file: file.to_string(),
+                source: source.to_string(),
+                expected: normalize(expected),
+                annotated: true,
             }
         }
 
-        let input = test_code.join("\n");
+        // The lines the errors of a run are compared as
+        fn errors(&self, errors: &[MossError]) -> Vec<String> {
+            if !self.annotated {
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                return ["ERROR:".to_string()]
+                    .into_iter()
+                    .chain(messages.join("\n").lines().map(String::from))
+                    .collect();
+            }
 
-        let output = Command::new("cargo")
-            .arg("run")
-            .arg("e")
-            .arg(input)
-            .output()
-            .unwrap();
-        let lines = std::str::from_utf8(output.stdout.as_slice())
-            .unwrap()
-            .split("\n")
-            .collect::<Vec<&str>>();
-
-        if !(lines.len() == expected_output.len() || lines.len() == expected_output.len() + 1) {
-            return Err(format!(
-                "{:#?}: output length does not match expected output: {} != {}\nFull output:\n{}",
-                file.file_name(),
-                lines.len(),
-                expected_output.len(),
-                lines.join("\n")
-            ));
+            errors
+                .iter()
+                .map(|error| {
+                    let kind = match error.kind {
+                        ErrorKind::Runtime | ErrorKind::Io => "runtime error",
+                        _ => "compile error",
+                    };
+                    let in_case = error.file.as_ref().unwrap_or(&self.file) == &self.file;
+                    match error.span {
+                        Some(span) if in_case => {
+                            format!("[line {}] {}: {}", span.line, kind, error.message)
+                        }
+                        _ => format!("{}: {}", kind, error.message),
+                    }
+                })
+                .collect()
         }
+    }
 
-        for (i, expected) in expected_output.iter().enumerate() {
-            if lines[i] != (*expected).trim() {
-                return Err(format!(
-                    "{:#?}: {} != {}\nFull output:\n{}",
-                    file.file_name(),
-                    lines[i],
-                    expected,
-                    lines.join("\n")
-                ));
+    // The expected and actual lines side by side, with `-` marking expected
+    // lines that are missing and `+` the lines printed instead
+    fn diff(expected: &[String], actual: &[String]) -> String {
+        let mut lines = vec![];
+        for i in 0..expected.len().max(actual.len()) {
+            match (expected.get(i), actual.get(i)) {
+                (Some(e), Some(a)) if e == a => lines.push(format!("  {}", e)),
+                (e, a) => {
+                    if let Some(e) = e {
+                        lines.push(format!("- {}", e));
+                    }
+                    if let Some(a) = a {
+                        lines.push(format!("+ {}", a));
+                    }
+                }
             }
         }
-
-        Ok(())
+        lines.join("\n")
     }
 }
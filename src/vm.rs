@@ -0,0 +1,837 @@
+use crate::chunk::{FunctionProto, OpCode};
+use crate::compiler::Compiler;
+use crate::environment::get_globals;
+use crate::error::{ErrorKind, Frame, MossError, SCRIPT};
+use crate::expr::{
+    binary_op, check_arity, get_index, get_property, set_index, set_property, unary_op,
+    CallableImpl, LiteralValue, LoxClassImpl,
+};
+use crate::gc::{self, Trace};
+use crate::module::{exports, Modules};
+use crate::output::Output;
+use crate::process::ProcessPolicy;
+use crate::scanner::TokenType;
+use crate::stdlib;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub const FRAMES_MAX: usize = 4096;
+
+pub struct Closure {
+    pub function: Rc<FunctionProto>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    pub module: Rc<ModuleScope>,
+}
+
+/// The globals of a module and the file it was loaded from. Every closure
+/// created in a module shares them, wherever it is called from.
+pub struct ModuleScope {
+    pub globals: RefCell<HashMap<String, LiteralValue>>,
+    pub file: String,
+}
+
+impl ModuleScope {
+    // Closures keep the scope of their module alive and globals can hold
+    // them, so the cycle collector tracks module scopes
+    fn new(globals: HashMap<String, LiteralValue>, file: &str) -> Rc<Self> {
+        let module = Rc::new(ModuleScope {
+            globals: RefCell::new(globals),
+            file: file.to_string(),
+        });
+        gc::track(&module);
+        module
+    }
+}
+
+impl Trace for ModuleScope {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        match self.globals.try_borrow() {
+            Ok(globals) => {
+                globals.values().for_each(|value| value.trace(visit));
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut globals) = self.globals.try_borrow_mut() {
+            globals.clear();
+        }
+    }
+}
+
+impl Trace for Closure {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        self.upvalues
+            .iter()
+            .for_each(|upvalue| visit(gc::address(upvalue)));
+        visit(gc::address(&self.module));
+        true
+    }
+
+    // The upvalues are cleared themselves if nothing else refers to them
+    fn clear(&self) {}
+}
+
+impl Trace for RefCell<Upvalue> {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        match self.try_borrow() {
+            Ok(upvalue) => {
+                if let Upvalue::Closed(value) = &*upvalue {
+                    value.trace(visit);
+                }
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    // An open upvalue refers to a live stack slot, so only closed ones can be
+    // garbage
+    fn clear(&self) {
+        if let Ok(mut upvalue) = self.try_borrow_mut() {
+            *upvalue = Upvalue::Closed(LiteralValue::Nil);
+        }
+    }
+}
+
+// What stops `execute`: a message about the current instruction, a call that
+// failed inside the function it called, such as a native returning an error,
+// or a failed import whose error already points into the imported module
+enum VmError {
+    Message(String),
+    Call { function: String, message: String },
+    Import(MossError),
+}
+
+// Makes an error of a call to `function` happen inside it, like the errors
+// raised by the code it runs
+fn in_call(function: &str) -> impl FnOnce(String) -> VmError + '_ {
+    move |message| VmError::Call {
+        function: function.to_string(),
+        message,
+    }
+}
+
+impl From<String> for VmError {
+    fn from(message: String) -> Self {
+        VmError::Message(message)
+    }
+}
+
+/// A captured variable. It points into the VM stack while the declaring frame
+/// is alive and owns the value once that frame returns.
+pub enum Upvalue {
+    Open(usize),
+    Closed(LiteralValue),
+}
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    base: usize,
+    // The class an initializer runs for, which names the call in traces
+    constructs: Option<Rc<LoxClassImpl>>,
+}
+
+pub struct Vm {
+    stack: Vec<LiteralValue>,
+    frames: Vec<CallFrame>,
+    pub module: Rc<ModuleScope>,
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    modules: Rc<Modules>,
+    process: Rc<ProcessPolicy>,
+    filesystem: bool,
+    output: Output,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self {
+            stack: vec![],
+            frames: vec![],
+            module: ModuleScope::new(get_globals().borrow().clone(), SCRIPT),
+            open_upvalues: vec![],
+            modules: Rc::new(Modules::default()),
+            process: Rc::new(ProcessPolicy::default()),
+            filesystem: true,
+            output: Output::default(),
+        }
+    }
+
+    /// Sets the file the program was loaded from
+    pub fn in_file(mut self, file: &str) -> Self {
+        self.module = ModuleScope::new(self.module.globals.take(), file);
+        self
+    }
+
+    /// Sets where `print` writes
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Makes the natives that touch the filesystem fail
+    pub fn without_filesystem(mut self) -> Self {
+        self.filesystem = false;
+        stdlib::io::disable_filesystem(&mut self.module.globals.borrow_mut());
+        self
+    }
+
+    /// Sets which commands the program may run
+    pub fn with_process_policy(mut self, policy: ProcessPolicy) -> Self {
+        self.process = Rc::new(policy);
+        self
+    }
+
+    // A VM with fresh globals for a module imported by this program
+    fn for_module(&self, file: &str) -> Vm {
+        let mut vm = Vm::new().in_file(file);
+        vm.modules = self.modules.clone();
+        vm.process = self.process.clone();
+        vm.output = self.output.clone();
+        if !self.filesystem {
+            vm = vm.without_filesystem();
+        }
+        vm
+    }
+
+    pub fn interpret(&mut self, function: FunctionProto) -> Result<LiteralValue, MossError> {
+        let closure = Rc::new(Closure {
+            function: Rc::new(function),
+            upvalues: vec![],
+            module: self.module.clone(),
+        });
+        self.call(
+            LiteralValue::Callable(CallableImpl::Closure(closure)),
+            vec![],
+        )
+    }
+
+    /// Calls a function or class with `args` and runs it until it returns
+    pub fn call(
+        &mut self,
+        callee: LiteralValue,
+        args: Vec<LiteralValue>,
+    ) -> Result<LiteralValue, MossError> {
+        let arg_count = args.len();
+        self.stack.push(callee);
+        self.stack.extend(args);
+
+        let result = match self.call_value(arg_count) {
+            // Natives and classes without an initializer have already returned
+            Ok(()) if self.frames.is_empty() => Ok(()),
+            Ok(()) => self.run(),
+            Err(VmError::Message(message) | VmError::Call { message, .. }) => {
+                Err(MossError::new(ErrorKind::Runtime, message, None))
+            }
+            Err(VmError::Import(error)) => Err(error),
+        };
+        match result {
+            Ok(()) => Ok(self.pop()),
+            Err(error) => {
+                // Leave the VM usable for the next chunk, e.g. in the REPL
+                self.stack.clear();
+                self.frames.clear();
+                self.open_upvalues.clear();
+                Err(error)
+            }
+        }
+    }
+
+    fn run(&mut self) -> Result<(), MossError> {
+        let mut ip = 0;
+        self.execute(&mut ip).map_err(|error| {
+            // `ip` has already moved past the instruction that failed
+            let frame = self.frames.last().expect("No active call frame");
+            let span = frame.closure.function.chunk.spans[ip - 1];
+            match error {
+                VmError::Message(message) => {
+                    MossError::new(ErrorKind::Runtime, message, Some(span))
+                        .in_file(&frame.closure.module.file)
+                        .with_trace(self.stack_trace())
+                }
+                VmError::Call { function, message } => {
+                    let file = frame.closure.module.file.clone();
+                    let call = Frame {
+                        function,
+                        call_site: span,
+                        file: file.clone(),
+                    };
+                    let trace = std::iter::once(call).chain(self.stack_trace()).collect();
+                    MossError::new(ErrorKind::Runtime, message, Some(span))
+                        .in_file(&file)
+                        .with_trace(trace)
+                }
+                VmError::Import(error) => error,
+            }
+        })
+    }
+
+    // The active calls, innermost first. Each caller's `ip` was saved just past
+    // its `Call` instruction.
+    fn stack_trace(&self) -> Vec<Frame> {
+        self.frames
+            .windows(2)
+            .rev()
+            .map(|pair| Frame {
+                function: match &pair[1].constructs {
+                    Some(class) => class.name.clone(),
+                    None => pair[1].closure.function.name.clone(),
+                },
+                call_site: pair[0].closure.function.chunk.spans[pair[0].ip - 1],
+                file: pair[0].closure.module.file.clone(),
+            })
+            .collect()
+    }
+
+    /// Runs until the outermost frame returns, leaving its result on the stack.
+    /// `ip` is kept up to date with the instruction pointer of the innermost
+    /// frame, so that `run` can find where an error happened.
+    fn execute(&mut self, ip: &mut usize) -> Result<(), VmError> {
+        let (mut closure, start, mut base) = self.current_frame();
+        *ip = start;
+
+        loop {
+            let op = closure.function.chunk.code[*ip];
+            *ip += 1;
+
+            match op {
+                OpCode::Constant(idx) => {
+                    let value = closure.function.chunk.constants[idx].clone();
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(LiteralValue::Nil),
+                OpCode::True => self.stack.push(LiteralValue::True),
+                OpCode::False => self.stack.push(LiteralValue::False),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::Dup(depth) => {
+                    let value = self.stack[self.stack.len() - 1 - depth].clone();
+                    self.stack.push(value);
+                }
+                OpCode::PopBelow(count) => {
+                    let top = self.pop();
+                    self.stack.truncate(self.stack.len() - count);
+                    self.stack.push(top);
+                }
+                OpCode::GetLocal(slot) => {
+                    let value = self.stack[base + slot].clone();
+                    self.stack.push(value);
+                }
+                OpCode::SetLocal(slot) => {
+                    self.stack[base + slot] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal(idx) => {
+                    let name = constant_name(&closure, idx);
+                    let value = closure.module.globals.borrow().get(name).cloned();
+                    match value {
+                        Some(value) => self.stack.push(value),
+                        None => {
+                            return Err(format!("Variable '{}' has not been declared", name).into())
+                        }
+                    }
+                }
+                OpCode::DefineGlobal(idx) => {
+                    let name = constant_name(&closure, idx).to_string();
+                    let value = self.pop();
+                    closure.module.globals.borrow_mut().insert(name, value);
+                }
+                OpCode::SetGlobal(idx) => {
+                    let name = constant_name(&closure, idx);
+                    let mut globals = closure.module.globals.borrow_mut();
+                    if !globals.contains_key(name) {
+                        return Err(format!("Variable {} has not been declared", name).into());
+                    }
+                    globals.insert(name.to_string(), self.peek(0).clone());
+                }
+                OpCode::GetUpvalue(idx) => {
+                    let value = match &*closure.upvalues[idx].borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue(idx) => {
+                    let value = self.peek(0).clone();
+                    match &mut *closure.upvalues[idx].borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetProperty(idx) => {
+                    let name = constant_name(&closure, idx);
+                    let object = self.pop();
+                    let value = get_property(&object, name)?;
+                    self.stack.push(value);
+                }
+                OpCode::SetProperty(idx) => {
+                    let name = constant_name(&closure, idx);
+                    let value = self.pop();
+                    let object = self.pop();
+                    set_property(&object, name, value)?;
+                    self.stack.push(LiteralValue::Nil);
+                }
+                OpCode::GetSuper(idx) => {
+                    let name = constant_name(&closure, idx);
+                    let superclass = self.pop();
+                    let instance = self.pop();
+                    let method = match &superclass {
+                        LiteralValue::LoxClass(class) => class.find_method(name).ok_or_else(|| {
+                            format!("No method named {} on superclass {}", name, class.name)
+                        }),
+                        other => Err(format!(
+                            "Superclass must be a class, not {}",
+                            other.to_type()
+                        )),
+                    }?;
+                    self.stack
+                        .push(LiteralValue::Callable(method.bind(instance)));
+                }
+                OpCode::BuildList(count) => {
+                    let elements = self.stack.split_off(self.stack.len() - count);
+                    self.stack.push(LiteralValue::list(elements));
+                }
+                OpCode::BuildMap(count) => {
+                    let members = self.stack.split_off(self.stack.len() - 2 * count);
+                    let mut entries = HashMap::new();
+                    for member in members.chunks(2) {
+                        if let LiteralValue::StringValue(key) = &member[0] {
+                            entries.insert(key.clone(), member[1].clone());
+                        }
+                    }
+                    self.stack.push(LiteralValue::map(entries));
+                }
+                OpCode::GetIndex => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    self.stack.push(get_index(&object, &index)?);
+                }
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let object = self.pop();
+                    set_index(&object, &index, value.clone())?;
+                    self.stack.push(value);
+                }
+                OpCode::Equal => self.binary(TokenType::EqualEqual)?,
+                OpCode::NotEqual => self.binary(TokenType::BangEqual)?,
+                OpCode::Greater => self.binary(TokenType::Greater)?,
+                OpCode::GreaterEqual => self.binary(TokenType::GreaterEqual)?,
+                OpCode::Less => self.binary(TokenType::Less)?,
+                OpCode::LessEqual => self.binary(TokenType::LessEqual)?,
+                OpCode::Add => self.binary(TokenType::Plus)?,
+                OpCode::Subtract => self.binary(TokenType::Minus)?,
+                OpCode::Multiply => self.binary(TokenType::Star)?,
+                OpCode::Divide => self.binary(TokenType::Slash)?,
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(unary_op(TokenType::Bang, &value)?);
+                }
+                OpCode::Negate => {
+                    let value = self.pop();
+                    self.stack.push(unary_op(TokenType::Minus, &value)?);
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    self.output.print(&value.to_string())?;
+                }
+                OpCode::Jump(target) => *ip = target,
+                OpCode::JumpIfFalse(target) => {
+                    if self.peek(0).is_truthy() == LiteralValue::False {
+                        *ip = target;
+                    }
+                }
+                OpCode::Call(arg_count) => {
+                    self.frames.last_mut().expect("No active call frame").ip = *ip;
+                    self.call_value(arg_count)?;
+                    (closure, *ip, base) = self.current_frame();
+                }
+                OpCode::Closure(idx) => {
+                    let function = closure.function.chunk.functions[idx].clone();
+                    let upvalues = function
+                        .upvalues
+                        .iter()
+                        .map(|upvalue| {
+                            if upvalue.is_local {
+                                self.capture_upvalue(base + upvalue.index)
+                            } else {
+                                closure.upvalues[upvalue.index].clone()
+                            }
+                        })
+                        .collect();
+                    let new_closure = Rc::new(Closure {
+                        function,
+                        upvalues,
+                        module: closure.module.clone(),
+                    });
+                    gc::track(&new_closure);
+                    self.stack
+                        .push(LiteralValue::Callable(CallableImpl::Closure(new_closure)));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    self.close_upvalues(base);
+                    self.frames.pop();
+                    self.stack.truncate(base);
+                    self.stack.push(result);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    (closure, *ip, base) = self.current_frame();
+                }
+                OpCode::Class(idx) => {
+                    let name = constant_name(&closure, idx).to_string();
+                    self.stack.push(LiteralValue::class(LoxClassImpl {
+                        name,
+                        methods: HashMap::new(),
+                        superclass: None,
+                    }));
+                }
+                OpCode::Inherit => {
+                    let superclass = self.peek(1).clone();
+                    if let LiteralValue::LoxClass(superclass) = superclass {
+                        self.build_class(|class| class.superclass = Some(superclass));
+                    } else {
+                        return Err(format!(
+                            "Superclass must be a class, not {}",
+                            superclass.to_type()
+                        )
+                        .into());
+                    }
+                }
+                OpCode::Import(idx) => {
+                    let path = constant_name(&closure, idx);
+                    let span = closure.function.chunk.spans[*ip - 1];
+                    let modules = self.modules.clone();
+                    let exports = modules
+                        .import(path, &closure.module.file, span, |module| {
+                            let function =
+                                Compiler::new().compile(&module.stmts.iter().collect())?;
+                            let mut vm = self.for_module(&module.file);
+                            vm.interpret(function)?;
+
+                            let globals = vm.module.globals.borrow();
+                            exports(&module.stmts, |name| globals.get(name).cloned())
+                        })
+                        .map_err(VmError::Import)?;
+                    self.stack.push(exports);
+                }
+                OpCode::Command(idx) => {
+                    let command = &closure.function.chunk.commands[idx];
+                    self.stack.push(command.native(self.process.clone()));
+                }
+                OpCode::Method(idx) => {
+                    let name = constant_name(&closure, idx).to_string();
+                    if let LiteralValue::Callable(method) = self.pop() {
+                        self.build_class(|class| {
+                            class.methods.insert(name, method);
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    // Changes the class being declared on top of the stack. Nothing refers to
+    // it yet, so it is taken apart and tracked again once changed.
+    fn build_class(&mut self, build: impl FnOnce(&mut LoxClassImpl)) {
+        if let Some(LiteralValue::LoxClass(class)) = self.stack.pop() {
+            let mut class = Rc::unwrap_or_clone(class);
+            build(&mut class);
+            self.stack.push(LiteralValue::class(class));
+        }
+    }
+
+    fn current_frame(&self) -> (Rc<Closure>, usize, usize) {
+        let frame = self.frames.last().expect("No active call frame");
+        (frame.closure.clone(), frame.ip, frame.base)
+    }
+
+    fn call_value(&mut self, arg_count: usize) -> Result<(), VmError> {
+        let callee_slot = self.stack.len() - arg_count - 1;
+        let callee = self.stack[callee_slot].clone();
+
+        match callee {
+            LiteralValue::Callable(CallableImpl::Closure(closure)) => self
+                .call_closure(closure.clone(), arg_count, None)
+                .map_err(in_call(&closure.function.name)),
+            LiteralValue::Callable(CallableImpl::BoundMethod(bound)) => {
+                self.stack[callee_slot] = *bound.receiver;
+                match *bound.method {
+                    CallableImpl::Closure(method) => self
+                        .call_closure(method.clone(), arg_count, None)
+                        .map_err(in_call(&method.function.name)),
+                    other => Err(format!("{} is not callable", other.name()).into()),
+                }
+            }
+            LiteralValue::Callable(CallableImpl::NativeFunction(native)) => {
+                check_arity(&native.name, native.arity, arg_count)
+                    .map_err(in_call(&native.name))?;
+                let args = self.stack.split_off(callee_slot + 1);
+                self.pop();
+                let result = (native.fun)(&args).map_err(in_call(&native.name))?;
+                self.stack.push(result);
+                Ok(())
+            }
+            LiteralValue::LoxClass(class) => {
+                let instance = LiteralValue::instance(class.clone());
+                self.stack[callee_slot] = instance;
+
+                // Call the constructor, which may be inherited. A class
+                // without one takes no arguments.
+                let init = class.find_method("init");
+                if init.as_ref().map_or(0, CallableImpl::arity) != arg_count {
+                    let message = "Invalid number of arguments in constructor";
+                    return Err(in_call(&class.name)(message.to_string()));
+                }
+                match init {
+                    Some(CallableImpl::Closure(init)) => self
+                        .call_closure(init, arg_count, Some(class.clone()))
+                        .map_err(in_call(&class.name)),
+                    _ => Ok(()),
+                }
+            }
+            other => Err(format!("{} is not callable", other.to_type()).into()),
+        }
+    }
+
+    fn call_closure(
+        &mut self,
+        closure: Rc<Closure>,
+        arg_count: usize,
+        constructs: Option<Rc<LoxClassImpl>>,
+    ) -> Result<(), String> {
+        check_arity(&closure.function.name, closure.function.arity, arg_count)?;
+
+        if self.frames.len() >= FRAMES_MAX {
+            return Err("Stack overflow".to_string());
+        }
+
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            base: self.stack.len() - arg_count - 1,
+            constructs,
+        });
+
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        for upvalue in &self.open_upvalues {
+            if let Upvalue::Open(open_slot) = *upvalue.borrow() {
+                if open_slot == slot {
+                    return upvalue.clone();
+                }
+            }
+        }
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        gc::track(&upvalue);
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) => slot,
+                Upvalue::Closed(_) => return false,
+            };
+            if slot >= from {
+                *upvalue.borrow_mut() = Upvalue::Closed(stack[slot].clone());
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    fn binary(&mut self, operator: TokenType) -> Result<(), String> {
+        let right = self.pop();
+        let left = self.pop();
+        let result = match (&left, operator, &right) {
+            (LiteralValue::Number(x), TokenType::Plus, LiteralValue::Number(y)) => {
+                LiteralValue::Number(x + y)
+            }
+            (LiteralValue::Number(x), TokenType::Minus, LiteralValue::Number(y)) => {
+                LiteralValue::Number(x - y)
+            }
+            (LiteralValue::Number(x), TokenType::Less, LiteralValue::Number(y)) => {
+                LiteralValue::from_bool(x < y)
+            }
+            _ => binary_op(&left, operator, &right)?,
+        };
+        self.stack.push(result);
+
+        Ok(())
+    }
+
+    fn pop(&mut self) -> LiteralValue {
+        self.stack.pop().expect("Stack underflow")
+    }
+
+    fn peek(&self, distance: usize) -> &LiteralValue {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+}
+
+fn constant_name(closure: &Closure, idx: usize) -> &str {
+    match &closure.function.chunk.constants[idx] {
+        LiteralValue::StringValue(name) => name,
+        other => panic!("Expected a name constant, got {}", other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn run_vm(source: &str) -> Result<Vm, String> {
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().map_err(|e| e[0].to_string())?;
+        let mut parser = Parser::new(tokens);
+        let stmts = parser.parse().map_err(|e| e[0].to_string())?;
+        let function = Compiler::new()
+            .compile(&stmts.iter().collect())
+            .map_err(|e| e.to_string())?;
+
+        let mut vm = Vm::new();
+        vm.interpret(function).map_err(|e| e.to_string())?;
+        Ok(vm)
+    }
+
+    #[test]
+    fn arithmetic_and_globals() {
+        let vm = run_vm("var a = 1 + 2 * 3; var b = a - 1; var c = \"a\" + \"b\";").unwrap();
+        assert_eq!(vm.module.globals.borrow()["a"], LiteralValue::Number(7.0));
+        assert_eq!(vm.module.globals.borrow()["b"], LiteralValue::Number(6.0));
+        assert_eq!(
+            vm.module.globals.borrow()["c"],
+            LiteralValue::StringValue("ab".to_string())
+        );
+    }
+
+    #[test]
+    fn return_from_nested_loop() {
+        let source = r#"
+            fun first_over(limit) {
+                var i = 0;
+                while (true) {
+                    if (i > limit) { return i; }
+                    i = i + 1;
+                }
+            }
+            var result = first_over(4);
+        "#;
+        let vm = run_vm(source).unwrap();
+        assert_eq!(
+            vm.module.globals.borrow()["result"],
+            LiteralValue::Number(5.0)
+        );
+    }
+
+    #[test]
+    fn break_and_continue_discard_loop_locals() {
+        let source = r#"
+            fun sum() {
+                var before = 1;
+                for (var i = 0; i < 5; i = i + 1) {
+                    var skipped = i * 10;
+                    if (i == 1) { var more = 0; continue; }
+                    if (i == 3) { break; }
+                    before = before + i;
+                }
+                var after = 100;
+                return before + after;
+            }
+            var result = sum();
+        "#;
+        let vm = run_vm(source).unwrap();
+        assert_eq!(
+            vm.module.globals.borrow()["result"],
+            LiteralValue::Number(103.0)
+        );
+    }
+
+    #[test]
+    fn closures_share_captured_variables() {
+        let source = r#"
+            fun make() {
+                var count = 0;
+                fun inc() { count = count + 1; return count; }
+                fun get() { return count; }
+                inc();
+                inc();
+                return get;
+            }
+            var result = make()();
+        "#;
+        let vm = run_vm(source).unwrap();
+        assert_eq!(
+            vm.module.globals.borrow()["result"],
+            LiteralValue::Number(2.0)
+        );
+    }
+
+    #[test]
+    fn methods_and_super_calls() {
+        let source = r#"
+            class A {
+                init(x) { this.x = x; }
+                value() { return this.x; }
+            }
+            class B < A {
+                init(x) { super.init(x); }
+                value() { return super.value() * 10; }
+            }
+            var result = B(4).value();
+        "#;
+        let vm = run_vm(source).unwrap();
+        assert_eq!(
+            vm.module.globals.borrow()["result"],
+            LiteralValue::Number(40.0)
+        );
+    }
+
+    #[test]
+    fn runtime_error_resets_vm() {
+        let mut scanner = Scanner::new("fun f() { return 1 + nil; } f();");
+        let tokens = scanner.scan_tokens().unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        let function = Compiler::new().compile(&stmts.iter().collect()).unwrap();
+
+        let mut vm = Vm::new();
+        assert!(vm.interpret(function).is_err());
+        assert!(vm.stack.is_empty());
+        assert!(vm.frames.is_empty());
+    }
+
+    #[test]
+    fn runtime_error_records_calls() {
+        let source = "fun inner() { return 1 + nil; }\nfun outer() {\n  inner();\n}\nouter();";
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        let function = Compiler::new().compile(&stmts.iter().collect()).unwrap();
+
+        let error = Vm::new().interpret(function).unwrap_err();
+        let calls: Vec<(&str, usize)> = error
+            .trace
+            .iter()
+            .map(|frame| (frame.function.as_str(), frame.call_site.line))
+            .collect();
+        assert_eq!(calls, vec![("inner", 3), ("outer", 5)]);
+    }
+}